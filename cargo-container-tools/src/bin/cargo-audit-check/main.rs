@@ -0,0 +1,231 @@
+#![deny(warnings)]
+#![deny(clippy::all)]
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+use cargo::core::Shell;
+use cargo::util::CargoResult;
+
+use clap::{crate_authors, crate_version, App, Arg, ArgMatches};
+use failure::{bail, format_err, ResultExt};
+use semver::{Version, VersionReq};
+use serde_derive::Deserialize;
+
+fn main() {
+    let matches = get_cli_app().get_matches();
+
+    if let Err(error) = run(&matches) {
+        cargo::handle_error(&error, &mut Shell::new());
+        exit(1);
+    }
+}
+
+fn get_cli_app() -> App<'static, 'static> {
+    App::new("cargo-audit-check")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Checks crates against a local RustSec advisory database")
+        .arg(
+            Arg::with_name("database")
+                .long("database")
+                .takes_value(true)
+                .value_name("DIR")
+                .required(true)
+                .help("Path to the advisory database checkout"),
+        )
+        .arg(
+            Arg::with_name("ignore")
+                .long("ignore")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("ID")
+                .help("Advisory to ignore"),
+        )
+        .arg(
+            Arg::with_name("warn")
+                .long("warn")
+                .help("Only print the found advisories, without failing"),
+        )
+        .arg(
+            Arg::with_name("packages")
+                .value_name("NAME@VERSION")
+                .multiple(true)
+                .help("Crates to check"),
+        )
+}
+
+/// Advisory in either the current (Markdown with TOML front matter) or legacy (TOML) format.
+#[derive(Deserialize)]
+struct AdvisoryFile {
+    advisory: Advisory,
+
+    #[serde(default)]
+    versions: AffectedVersions,
+}
+
+#[derive(Deserialize)]
+struct Advisory {
+    id: String,
+    package: String,
+    title: Option<String>,
+    informational: Option<String>,
+    withdrawn: Option<String>,
+
+    #[serde(default)]
+    patched_versions: Vec<String>,
+
+    #[serde(default)]
+    unaffected_versions: Vec<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct AffectedVersions {
+    #[serde(default)]
+    patched: Vec<String>,
+
+    #[serde(default)]
+    unaffected: Vec<String>,
+}
+
+fn run(matches: &ArgMatches<'static>) -> CargoResult<()> {
+    let database = Path::new(matches.value_of("database").unwrap());
+    let ignored: BTreeSet<_> = matches.values_of("ignore").unwrap_or_default().collect();
+
+    if !database.join("crates").is_dir() {
+        bail!(
+            "'{}' doesn't look like an advisory database checkout",
+            database.display()
+        );
+    }
+
+    let mut vulnerabilities = 0;
+
+    for package in matches.values_of("packages").unwrap_or_default() {
+        let (name, version) = parse_package(package)?;
+
+        for advisory in read_advisories(database, name)? {
+            if advisory.withdrawn.is_some() || ignored.contains(advisory.id.as_str()) {
+                continue;
+            }
+
+            if !is_affected(&advisory, &version)? {
+                continue;
+            }
+
+            let kind = match advisory.informational {
+                Some(ref kind) => kind.as_str(),
+                None => {
+                    vulnerabilities += 1;
+                    "vulnerability"
+                }
+            };
+
+            eprintln!(
+                "{} {}: {} ({}) {}",
+                name,
+                version,
+                advisory.id,
+                kind,
+                advisory.title.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    if vulnerabilities > 0 && !matches.is_present("warn") {
+        bail!("Found {} unignored vulnerabilities", vulnerabilities);
+    }
+
+    Ok(())
+}
+
+fn parse_package(package: &str) -> CargoResult<(&str, Version)> {
+    let index = {
+        package
+            .rfind('@')
+            .ok_or_else(|| format_err!("Invalid package '{}', expected NAME@VERSION", package))?
+    };
+
+    let version = {
+        Version::parse(&package[index + 1..])
+            .with_context(|_| format!("Invalid version of '{}'", package))?
+    };
+
+    Ok((&package[..index], version))
+}
+
+fn read_advisories(database: &Path, name: &str) -> CargoResult<Vec<Advisory>> {
+    let dir = database.join("crates").join(name);
+    let mut advisories = vec![];
+
+    if !dir.is_dir() {
+        return Ok(advisories);
+    }
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let contents = {
+            fs::read_to_string(&path)
+                .with_context(|_| format!("Unable to read '{}'", path.display()))?
+        };
+
+        let front_matter = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("md") => toml_front_matter(&contents)
+                .ok_or_else(|| format_err!("Missing front matter in '{}'", path.display()))?,
+
+            Some("toml") => contents.as_str(),
+            _ => continue,
+        };
+
+        let file: AdvisoryFile = {
+            toml::from_str(front_matter)
+                .with_context(|_| format!("Unable to parse '{}'", path.display()))?
+        };
+
+        let mut advisory = file.advisory;
+
+        advisory.patched_versions.extend(file.versions.patched);
+        advisory
+            .unaffected_versions
+            .extend(file.versions.unaffected);
+
+        if advisory.package == name {
+            advisories.push(advisory);
+        }
+    }
+
+    advisories.sort_by(|left, right| left.id.cmp(&right.id));
+    Ok(advisories)
+}
+
+fn toml_front_matter(contents: &str) -> Option<&str> {
+    let start = contents.find("```toml")? + "```toml".len();
+    let end = contents[start..].find("```")?;
+
+    Some(&contents[start..start + end])
+}
+
+fn is_affected(advisory: &Advisory, version: &Version) -> CargoResult<bool> {
+    let requirements = {
+        advisory
+            .patched_versions
+            .iter()
+            .chain(advisory.unaffected_versions.iter())
+    };
+
+    for requirement in requirements {
+        let requirement = {
+            VersionReq::parse(requirement)
+                .with_context(|_| format!("Invalid version requirement in '{}'", advisory.id))?
+        };
+
+        if requirement.matches(version) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
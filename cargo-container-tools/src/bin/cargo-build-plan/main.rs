@@ -73,6 +73,26 @@ fn get_cli_app() -> App<'static, 'static> {
                     .multiple(true)
                     .help("Target triple for which the code is compiled")
             },
+            {
+                Arg::with_name("offline")
+                    .long("offline")
+                    .takes_value(false)
+                    .help("Run without accessing the network")
+            },
+            {
+                Arg::with_name("unlocked")
+                    .long("unlocked")
+                    .takes_value(false)
+                    .help("Allow Cargo.lock to be updated")
+            },
+            {
+                Arg::with_name("frozen")
+                    .long("frozen")
+                    .takes_value(false)
+                    .help(
+                    "Require Cargo.lock and cache are up to date, without accessing the network",
+                )
+            },
         ])
 }
 
@@ -107,6 +127,18 @@ fn run(matches: &ArgMatches<'static>) -> CargoResult<()> {
         process.arg("--feature").arg(feature);
     }
 
+    if matches.is_present("offline") {
+        process.arg("--offline");
+    }
+
+    if matches.is_present("unlocked") {
+        process.arg("--unlocked");
+    }
+
+    if matches.is_present("frozen") {
+        process.arg("--frozen");
+    }
+
     let mut child = process.spawn()?;
 
     copy(&mut child.stdout.take().unwrap(), &mut writer)
@@ -123,7 +155,16 @@ fn run(matches: &ArgMatches<'static>) -> CargoResult<()> {
 
 fn run_stdout(matches: &ArgMatches<'static>) -> CargoResult<()> {
     let mut config = Config::default()?;
-    config.configure(0, None, &None, false, true, false, &None, &[])?;
+    config.configure(
+        0,
+        None,
+        &None,
+        matches.is_present("frozen"),
+        !matches.is_present("unlocked"),
+        matches.is_present("offline"),
+        &None,
+        &[],
+    )?;
 
     let mut build_config = BuildConfig::new(&config, Some(1), &None, CompileMode::Build)?;
     build_config.release = matches.is_present("release");
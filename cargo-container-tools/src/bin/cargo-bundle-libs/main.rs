@@ -0,0 +1,426 @@
+#![deny(warnings)]
+#![deny(clippy::all)]
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use cargo::core::Shell;
+use cargo::util::CargoResult;
+
+use clap::{crate_authors, crate_version, App, Arg, ArgMatches};
+use failure::{bail, format_err, ResultExt};
+use goblin::elf::dynamic::{DT_RPATH, DT_RUNPATH};
+use goblin::elf::Elf;
+
+const LD_SO_CONF: &str = "/etc/ld.so.conf";
+const DEFAULT_LIBRARY_DIRS: &[&str] = &["/lib", "/usr/lib", "/lib64", "/usr/lib64"];
+
+fn main() {
+    let matches = get_cli_app().get_matches();
+
+    if let Err(error) = run(&matches) {
+        cargo::handle_error(&error, &mut Shell::new());
+        exit(1);
+    }
+}
+
+fn get_cli_app() -> App<'static, 'static> {
+    App::new("cargo-bundle-libs")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Collects shared libraries required by dynamically linked binaries")
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .value_name("DIR")
+                .required(true)
+                .help("Directory where the libraries should be copied to"),
+        )
+        .arg(
+            Arg::with_name("binaries")
+                .value_name("BINARY")
+                .multiple(true)
+                .help("Binaries to collect the libraries for"),
+        )
+}
+
+fn run(matches: &ArgMatches<'static>) -> CargoResult<()> {
+    let output = PathBuf::from(matches.value_of("output").unwrap());
+    let resolver = Resolver::from_env()?;
+
+    let mut collected = BTreeSet::new();
+
+    for binary in matches.values_of("binaries").unwrap_or_default() {
+        resolver
+            .collect(Path::new(binary), &mut collected)
+            .with_context(|_| format!("Unable to collect libraries for '{}'", binary))?;
+    }
+
+    for path in collected {
+        let destination = output.join(path.strip_prefix("/").unwrap_or(&path));
+
+        fs::create_dir_all(destination.parent().unwrap())
+            .context("Unable to create libraries directory")?;
+
+        fs::copy(&path, &destination)
+            .with_context(|_| format!("Unable to copy '{}'", path.display()))?;
+    }
+
+    Ok(())
+}
+
+struct Resolver {
+    env_dirs: Vec<PathBuf>,
+    system_dirs: Vec<PathBuf>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct ElfKind {
+    is_64: bool,
+    machine: u16,
+}
+
+impl Resolver {
+    fn from_env() -> CargoResult<Self> {
+        let env_dirs = {
+            env::var("LD_LIBRARY_PATH")
+                .map(|value| split_paths(&value))
+                .unwrap_or_default()
+        };
+
+        let mut system_dirs = vec![];
+
+        if Path::new(LD_SO_CONF).exists() {
+            read_ld_so_conf(Path::new(LD_SO_CONF), &mut system_dirs)?;
+        }
+
+        system_dirs.extend(DEFAULT_LIBRARY_DIRS.iter().map(PathBuf::from));
+
+        Ok(Self {
+            env_dirs,
+            system_dirs,
+        })
+    }
+
+    fn collect(&self, binary: &Path, collected: &mut BTreeSet<PathBuf>) -> CargoResult<()> {
+        let contents = fs::read(binary).context("Unable to read the binary")?;
+        let elf = Elf::parse(&contents).context("Unable to parse ELF file")?;
+
+        let interpreter = match elf.interpreter {
+            Some(interpreter) => PathBuf::from(interpreter),
+            None => {
+                // Statically linked binaries don't need anything.
+                return Ok(());
+            }
+        };
+
+        collected.insert(interpreter);
+        self.collect_needed(binary, &elf, ElfKind::of(&elf), collected)
+    }
+
+    fn collect_needed(
+        &self,
+        object: &Path,
+        elf: &Elf,
+        kind: ElfKind,
+        collected: &mut BTreeSet<PathBuf>,
+    ) -> CargoResult<()> {
+        let origin = object.parent().unwrap_or_else(|| Path::new("/"));
+
+        let expand = |tag: u64| -> Vec<PathBuf> {
+            dynamic_strings(elf, tag)
+                .iter()
+                .flat_map(|value| expand_origin(value, origin))
+                .collect()
+        };
+
+        let rpaths = expand(DT_RPATH);
+        let runpaths = expand(DT_RUNPATH);
+
+        for library in &elf.libraries {
+            let path = {
+                self.find(library, kind, &rpaths, &runpaths)
+                    .ok_or_else(|| format_err!("Unable to find library '{}'", library))?
+            };
+
+            if !collected.insert(path.clone()) {
+                continue;
+            }
+
+            let contents = {
+                fs::read(&path).with_context(|_| format!("Unable to read '{}'", path.display()))?
+            };
+
+            let library_elf = {
+                Elf::parse(&contents)
+                    .with_context(|_| format!("Unable to parse '{}'", path.display()))?
+            };
+
+            self.collect_needed(&path, &library_elf, kind, collected)?;
+        }
+
+        Ok(())
+    }
+
+    fn find(
+        &self,
+        name: &str,
+        kind: ElfKind,
+        rpaths: &[PathBuf],
+        runpaths: &[PathBuf],
+    ) -> Option<PathBuf> {
+        if name.contains('/') {
+            return Some(PathBuf::from(name)).filter(|path| path.exists());
+        }
+
+        self.search_dirs(rpaths, runpaths)
+            .map(|dir| dir.join(name))
+            .find(|candidate| ElfKind::read(candidate) == Some(kind))
+    }
+
+    /// Directories in the order the dynamic loader searches them.
+    fn search_dirs<'a>(
+        &'a self,
+        rpaths: &'a [PathBuf],
+        runpaths: &'a [PathBuf],
+    ) -> impl Iterator<Item = &'a PathBuf> {
+        // `DT_RPATH` is ignored by the loader when `DT_RUNPATH` is present.
+        let rpaths = if runpaths.is_empty() { rpaths } else { &[] };
+
+        rpaths
+            .iter()
+            .chain(&self.env_dirs)
+            .chain(runpaths)
+            .chain(&self.system_dirs)
+    }
+}
+
+impl ElfKind {
+    fn of(elf: &Elf) -> Self {
+        Self {
+            is_64: elf.is_64,
+            machine: elf.header.e_machine,
+        }
+    }
+
+    fn read(path: &Path) -> Option<Self> {
+        let contents = fs::read(path).ok()?;
+
+        Elf::parse(&contents).ok().map(|elf| Self::of(&elf))
+    }
+}
+
+fn dynamic_strings<'a>(elf: &Elf<'a>, tag: u64) -> Vec<&'a str> {
+    let dynamic = match elf.dynamic {
+        Some(ref dynamic) => dynamic,
+        None => return vec![],
+    };
+
+    dynamic
+        .dyns
+        .iter()
+        .filter(|item| item.d_tag == tag)
+        .filter_map(|item| elf.dynstrtab.get(item.d_val as usize)?.ok())
+        .collect()
+}
+
+fn expand_origin(value: &str, origin: &Path) -> Vec<PathBuf> {
+    let origin = origin.to_string_lossy();
+
+    split_paths(
+        &value
+            .replace("${ORIGIN}", &origin)
+            .replace("$ORIGIN", &origin),
+    )
+}
+
+fn split_paths(value: &str) -> Vec<PathBuf> {
+    value
+        .split(&[':', ';'][..])
+        .filter(|item| !item.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn read_ld_so_conf(path: &Path, dirs: &mut Vec<PathBuf>) -> CargoResult<()> {
+    let contents = {
+        fs::read_to_string(path).with_context(|_| format!("Unable to read '{}'", path.display()))?
+    };
+
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(pattern) = line.strip_prefix("include") {
+            let pattern = pattern.trim();
+            let pattern = if Path::new(pattern).is_absolute() {
+                PathBuf::from(pattern)
+            } else {
+                path.parent().unwrap().join(pattern)
+            };
+
+            for entry in glob(&pattern)? {
+                read_ld_so_conf(&entry, dirs)?;
+            }
+
+            continue;
+        }
+
+        dirs.push(PathBuf::from(line));
+    }
+
+    Ok(())
+}
+
+/// Minimal glob support for `include` directives: only the file name may contain a `*`.
+fn glob(pattern: &Path) -> CargoResult<Vec<PathBuf>> {
+    let (dir, name) = match (pattern.parent(), pattern.file_name()) {
+        (Some(dir), Some(name)) => (dir, name.to_string_lossy()),
+        _ => bail!("Invalid include pattern '{}'", pattern.display()),
+    };
+
+    if !name.contains('*') {
+        return Ok(vec![pattern.into()]);
+    }
+
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut parts = name.splitn(2, '*');
+    let prefix = parts.next().unwrap_or_default();
+    let suffix = parts.next().unwrap_or_default();
+
+    let mut entries = vec![];
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        if file_name.len() >= prefix.len() + suffix.len()
+            && file_name.starts_with(prefix)
+            && file_name.ends_with(suffix)
+        {
+            entries.push(entry.path());
+        }
+    }
+
+    entries.sort();
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ld_so_conf_parsing() {
+        let root = env::temp_dir().join(format!("cargo-bundle-libs-{}", std::process::id()));
+        let includes = root.join("ld.so.conf.d");
+
+        fs::create_dir_all(&includes).unwrap();
+        fs::write(
+            root.join("ld.so.conf"),
+            "# Multiarch support\n/usr/local/lib\ninclude ld.so.conf.d/*.conf\n\n/opt/lib # vendor\n",
+        )
+        .unwrap();
+
+        fs::write(
+            includes.join("x86_64-linux-gnu.conf"),
+            "/lib/x86_64-linux-gnu\n",
+        )
+        .unwrap();
+        fs::write(includes.join("libc.conf"), "/usr/local/lib/libc\n").unwrap();
+        fs::write(includes.join("README"), "/ignored\n").unwrap();
+
+        let mut dirs = vec![];
+        let result = read_ld_so_conf(&root.join("ld.so.conf"), &mut dirs);
+
+        fs::remove_dir_all(&root).unwrap();
+        result.unwrap();
+
+        assert_eq!(
+            dirs,
+            vec![
+                PathBuf::from("/usr/local/lib"),
+                PathBuf::from("/usr/local/lib/libc"),
+                PathBuf::from("/lib/x86_64-linux-gnu"),
+                PathBuf::from("/opt/lib"),
+            ]
+        );
+    }
+
+    #[test]
+    fn origin_expansion() {
+        assert_eq!(
+            expand_origin(
+                "$ORIGIN/../lib:${ORIGIN}/plugins:/opt/lib",
+                Path::new("/app/bin")
+            ),
+            vec![
+                PathBuf::from("/app/bin/../lib"),
+                PathBuf::from("/app/bin/plugins"),
+                PathBuf::from("/opt/lib"),
+            ]
+        );
+
+        assert!(expand_origin("", Path::new("/app/bin")).is_empty());
+    }
+
+    #[test]
+    fn resolution_order() {
+        let resolver = Resolver {
+            env_dirs: vec![PathBuf::from("/env")],
+            system_dirs: vec![PathBuf::from("/system")],
+        };
+
+        let rpaths = vec![PathBuf::from("/rpath")];
+        let runpaths = vec![PathBuf::from("/runpath")];
+
+        assert_eq!(
+            resolver.search_dirs(&rpaths, &[]).collect::<Vec<_>>(),
+            vec![
+                &PathBuf::from("/rpath"),
+                &PathBuf::from("/env"),
+                &PathBuf::from("/system"),
+            ]
+        );
+
+        assert_eq!(
+            resolver.search_dirs(&rpaths, &runpaths).collect::<Vec<_>>(),
+            vec![
+                &PathBuf::from("/env"),
+                &PathBuf::from("/runpath"),
+                &PathBuf::from("/system"),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_library_path() {
+        let resolver = Resolver {
+            env_dirs: vec![],
+            system_dirs: vec![],
+        };
+
+        let kind = ElfKind {
+            is_64: true,
+            machine: 62,
+        };
+
+        assert_eq!(
+            resolver.find("/nonexistent/libmissing.so.1", kind, &[], &[]),
+            None
+        );
+
+        assert_eq!(resolver.find("libmissing.so.1", kind, &[], &[]), None);
+    }
+}
@@ -0,0 +1,230 @@
+#![deny(warnings)]
+#![deny(clippy::all)]
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use cargo::core::Shell;
+use cargo::util::CargoResult;
+
+use clap::{crate_authors, crate_version, App, Arg, ArgMatches};
+use failure::{bail, ResultExt};
+use serde_derive::Deserialize;
+
+const LICENSE_FILE_PREFIXES: &[&str] = &["license", "licence", "copying", "notice"];
+const SUMMARY_NAME: &str = "LICENSES.txt";
+
+fn main() {
+    let matches = get_cli_app().get_matches();
+
+    if let Err(error) = run(&matches) {
+        cargo::handle_error(&error, &mut Shell::new());
+        exit(1);
+    }
+}
+
+fn get_cli_app() -> App<'static, 'static> {
+    App::new("cargo-collect-licenses")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Collects license texts of the crates")
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .value_name("DIR")
+                .required(true)
+                .help("Directory where the license texts should be copied to"),
+        )
+        .arg(
+            Arg::with_name("deny")
+                .long("deny")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("LICENSE")
+                .help("SPDX license identifier that is not allowed"),
+        )
+        .arg(
+            Arg::with_name("packages")
+                .value_name("PACKAGE_DIR")
+                .multiple(true)
+                .help("Directories of the crates (containing 'Cargo.toml')"),
+        )
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    package: Package,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Package {
+    name: String,
+    version: String,
+    license: Option<String>,
+    license_file: Option<PathBuf>,
+}
+
+fn run(matches: &ArgMatches<'static>) -> CargoResult<()> {
+    let output = PathBuf::from(matches.value_of("output").unwrap());
+    let denied: BTreeSet<_> = matches.values_of("deny").unwrap_or_default().collect();
+
+    let mut summary = String::new();
+    let mut violations = vec![];
+
+    for dir in matches.values_of("packages").unwrap_or_default() {
+        let dir = Path::new(dir);
+        let package = {
+            read_package(dir)
+                .with_context(|_| format!("Unable to read the manifest in '{}'", dir.display()))?
+        };
+
+        let destination = output.join(format!("{}-{}", package.name, package.version));
+        let files = {
+            license_files(dir, &package)
+                .with_context(|_| format!("Unable to find license files of '{}'", package.name))?
+        };
+
+        fs::create_dir_all(&destination).context("Unable to create licenses directory")?;
+
+        for path in &files {
+            fs::copy(path, destination.join(path.file_name().unwrap()))
+                .with_context(|_| format!("Unable to copy '{}'", path.display()))?;
+        }
+
+        let expression = package.license.as_deref().unwrap_or("UNKNOWN");
+
+        if package.license.is_none() && files.is_empty() {
+            eprintln!(
+                "warning: no license information was found for '{} {}'",
+                package.name, package.version
+            );
+        }
+
+        if let Some(ref license) = package.license {
+            if !is_allowed(license, &denied) {
+                violations.push(format!(
+                    "{} {} ({})",
+                    package.name, package.version, license
+                ));
+            }
+        }
+
+        writeln!(
+            summary,
+            "{} {}: {}",
+            package.name, package.version, expression
+        )?;
+    }
+
+    if !violations.is_empty() {
+        bail!("Denied licenses were found: {}", violations.join(", "));
+    }
+
+    fs::create_dir_all(&output).context("Unable to create licenses directory")?;
+    fs::write(output.join(SUMMARY_NAME), summary).context("Unable to write licenses summary")?;
+
+    Ok(())
+}
+
+fn read_package(dir: &Path) -> CargoResult<Package> {
+    let contents = fs::read_to_string(dir.join("Cargo.toml"))?;
+    let manifest: Manifest = toml::from_str(&contents)?;
+
+    Ok(manifest.package)
+}
+
+fn license_files(dir: &Path, package: &Package) -> CargoResult<Vec<PathBuf>> {
+    let mut files = BTreeSet::new();
+
+    if let Some(ref path) = package.license_file {
+        files.insert(dir.join(path));
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_lowercase();
+
+        if entry.file_type()?.is_file()
+            && LICENSE_FILE_PREFIXES
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+        {
+            files.insert(entry.path());
+        }
+    }
+
+    Ok(files.into_iter().collect())
+}
+
+/// Checks whether an SPDX expression can be satisfied without the denied licenses.
+/// Legacy `/` separator is treated as `OR`, and license exceptions (`WITH`) are ignored.
+fn is_allowed(expression: &str, denied: &BTreeSet<&str>) -> bool {
+    if denied.is_empty() {
+        return true;
+    }
+
+    let spaced = expression
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .replace('/', " OR ");
+
+    let tokens: Vec<_> = spaced.split_whitespace().collect();
+    let mut position = 0;
+
+    parse_or(&tokens, &mut position, denied)
+}
+
+fn parse_or(tokens: &[&str], position: &mut usize, denied: &BTreeSet<&str>) -> bool {
+    let mut allowed = parse_and(tokens, position, denied);
+
+    while tokens.get(*position) == Some(&"OR") {
+        *position += 1;
+        allowed |= parse_and(tokens, position, denied);
+    }
+
+    allowed
+}
+
+fn parse_and(tokens: &[&str], position: &mut usize, denied: &BTreeSet<&str>) -> bool {
+    let mut allowed = parse_license(tokens, position, denied);
+
+    while tokens.get(*position) == Some(&"AND") {
+        *position += 1;
+        allowed &= parse_license(tokens, position, denied);
+    }
+
+    allowed
+}
+
+fn parse_license(tokens: &[&str], position: &mut usize, denied: &BTreeSet<&str>) -> bool {
+    let token = match tokens.get(*position) {
+        Some(token) => *token,
+        None => return true,
+    };
+
+    *position += 1;
+
+    let allowed = if token == "(" {
+        let allowed = parse_or(tokens, position, denied);
+
+        if tokens.get(*position) == Some(&")") {
+            *position += 1;
+        }
+
+        allowed
+    } else {
+        !denied.contains(token.trim_end_matches('+'))
+    };
+
+    if tokens.get(*position) == Some(&"WITH") {
+        *position += 2;
+    }
+
+    allowed
+}
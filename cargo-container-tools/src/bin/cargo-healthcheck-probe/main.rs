@@ -0,0 +1,285 @@
+#![deny(warnings)]
+#![deny(clippy::all)]
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::exit;
+use std::time::Duration;
+
+use cargo::core::Shell;
+use cargo::util::CargoResult;
+
+use clap::{crate_authors, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use failure::{bail, format_err, ResultExt};
+
+fn main() {
+    let matches = get_cli_app().get_matches();
+
+    if let Err(error) = run(&matches) {
+        cargo::handle_error(&error, &mut Shell::new());
+        exit(1);
+    }
+}
+
+fn get_cli_app() -> App<'static, 'static> {
+    let timeout = {
+        Arg::with_name("timeout")
+            .long("timeout")
+            .takes_value(true)
+            .value_name("SECONDS")
+            .default_value("5")
+            .help("Connection and response timeout")
+    };
+
+    App::new("cargo-healthcheck-probe")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Tiny static HTTP and TCP health probe")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("tcp")
+                .about("Check that a TCP connection can be established")
+                .arg(timeout.clone())
+                .arg(
+                    Arg::with_name("address")
+                        .value_name("HOST:PORT")
+                        .required(true)
+                        .help("Address to connect to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("http")
+                .about("Check that an HTTP endpoint responds with a successful status")
+                .arg(timeout)
+                .arg(
+                    Arg::with_name("url")
+                        .value_name("URL")
+                        .required(true)
+                        .help("Plain HTTP URL to request"),
+                ),
+        )
+}
+
+fn run(matches: &ArgMatches<'static>) -> CargoResult<()> {
+    match matches.subcommand() {
+        ("tcp", Some(matches)) => {
+            connect(matches.value_of("address").unwrap(), timeout(matches)?)?;
+        }
+
+        ("http", Some(matches)) => {
+            let url = HttpUrl::parse(matches.value_of("url").unwrap())?;
+            check_status(request(&url, timeout(matches)?)?)?;
+        }
+
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+fn timeout(matches: &ArgMatches) -> CargoResult<Duration> {
+    let seconds = {
+        matches
+            .value_of("timeout")
+            .unwrap()
+            .parse::<u64>()
+            .context("Invalid timeout")?
+    };
+
+    Ok(Duration::from_secs(seconds))
+}
+
+fn connect(address: &str, timeout: Duration) -> CargoResult<TcpStream> {
+    let addresses = {
+        address
+            .to_socket_addrs()
+            .with_context(|_| format!("Unable to resolve '{}'", address))?
+    };
+
+    let mut last_error = None;
+
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(timeout))?;
+                stream.set_write_timeout(Some(timeout))?;
+
+                return Ok(stream);
+            }
+
+            Err(error) => {
+                last_error = Some(error);
+            }
+        }
+    }
+
+    Err(match last_error {
+        Some(error) => format_err!("Unable to connect to '{}': {}", address, error),
+        None => format_err!("No addresses found for '{}'", address),
+    })
+}
+
+fn request(url: &HttpUrl, timeout: Duration) -> CargoResult<u16> {
+    let mut stream = connect(&format!("{}:{}", url.host, url.port), timeout)?;
+
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: cargo-healthcheck-probe\r\n\r\n",
+        url.path, url.host
+    )?;
+
+    let mut response = vec![];
+    stream
+        .take(1024)
+        .read_to_end(&mut response)
+        .context("Unable to read the response")?;
+
+    parse_status(&response)
+}
+
+fn parse_status(response: &[u8]) -> CargoResult<u16> {
+    let response = String::from_utf8_lossy(response);
+    let status_line = response.lines().next().unwrap_or_default();
+
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| format_err!("Malformed response: '{}'", status_line))
+}
+
+/// Successful and redirection statuses are healthy, the same as `curl -f` treats them.
+fn check_status(status: u16) -> CargoResult<()> {
+    if !(200..400).contains(&status) {
+        bail!("Unhealthy response status: {}", status);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+struct HttpUrl<'a> {
+    host: &'a str,
+    port: u16,
+    path: &'a str,
+}
+
+impl<'a> HttpUrl<'a> {
+    fn parse(url: &'a str) -> CargoResult<Self> {
+        let rest = match url.strip_prefix("http://") {
+            Some(rest) => rest,
+            None => bail!("Only plain 'http://' URLs are supported"),
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+
+        let (host, port) = match authority.rfind(':') {
+            Some(index) => (
+                &authority[..index],
+                authority[index + 1..]
+                    .parse::<u16>()
+                    .context("Invalid port")?,
+            ),
+
+            None => (authority, 80),
+        };
+
+        if host.is_empty() {
+            bail!("Missing host in '{}'", url);
+        }
+
+        Ok(Self { host, port, path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn url_parsing() {
+        assert_eq!(
+            HttpUrl::parse("http://localhost:8080/health?full=1").unwrap(),
+            HttpUrl {
+                host: "localhost",
+                port: 8080,
+                path: "/health?full=1",
+            }
+        );
+
+        assert_eq!(
+            HttpUrl::parse("http://127.0.0.1").unwrap(),
+            HttpUrl {
+                host: "127.0.0.1",
+                port: 80,
+                path: "/",
+            }
+        );
+
+        assert!(HttpUrl::parse("https://localhost/health").is_err());
+        assert!(HttpUrl::parse("localhost:8080").is_err());
+        assert!(HttpUrl::parse("http://:8080/health").is_err());
+        assert!(HttpUrl::parse("http://localhost:http/health").is_err());
+        assert!(HttpUrl::parse("http://localhost:70000").is_err());
+    }
+
+    #[test]
+    fn status_parsing() {
+        assert_eq!(
+            parse_status(b"HTTP/1.1 204 No Content\r\nServer: test\r\n\r\n").unwrap(),
+            204
+        );
+
+        assert_eq!(parse_status(b"HTTP/1.0 503\r\n\r\n").unwrap(), 503);
+
+        assert!(parse_status(b"").is_err());
+        assert!(parse_status(b"SSH-2.0-OpenSSH_8.0\r\n").is_err());
+    }
+
+    #[test]
+    fn status_check() {
+        assert!(check_status(200).is_ok());
+        assert!(check_status(302).is_ok());
+        assert!(check_status(399).is_ok());
+
+        assert!(check_status(199).is_err());
+        assert!(check_status(404).is_err());
+        assert!(check_status(500).is_err());
+    }
+
+    #[test]
+    fn http_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let size = stream.read(&mut request).unwrap();
+
+            stream
+                .write_all(b"HTTP/1.0 503 Service Unavailable\r\n\r\n")
+                .unwrap();
+
+            String::from_utf8_lossy(&request[..size]).into_owned()
+        });
+
+        let url = HttpUrl {
+            host: "127.0.0.1",
+            port,
+            path: "/health",
+        };
+
+        assert_eq!(request(&url, Duration::from_secs(5)).unwrap(), 503);
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /health HTTP/1.0\r\nHost: 127.0.0.1\r\n"));
+    }
+}
@@ -76,6 +76,8 @@ fn run(matches: &ArgMatches<'static>) -> CargoResult<()> {
 }
 
 fn package_metadata(package: &Package) -> Metadata {
+    let details = package.manifest().metadata();
+
     Metadata {
         origin: Origin::Package {
             name: package.name().to_string(),
@@ -83,6 +85,17 @@ fn package_metadata(package: &Package) -> Metadata {
         },
 
         metadata: package.manifest().custom_metadata().cloned(),
+
+        package: Some(PackageDetails {
+            name: package.name().to_string(),
+            version: package.version().clone(),
+            description: details.description.clone(),
+            authors: details.authors.clone(),
+            license: details.license.clone(),
+            homepage: details.homepage.clone(),
+            repository: details.repository.clone(),
+            documentation: details.documentation.clone(),
+        }),
     }
 }
 
@@ -90,6 +103,7 @@ fn workspace_metadata(ws: &Workspace) -> Vec<Metadata> {
     let root_metadata = Metadata {
         origin: Origin::WorkspaceRoot,
         metadata: workspace_root_inner_metadata(ws),
+        package: None,
     };
 
     once(root_metadata)
@@ -0,0 +1,436 @@
+#![deny(warnings)]
+#![deny(clippy::all)]
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use cargo::core::Shell;
+use cargo::util::CargoResult;
+
+use clap::{crate_authors, crate_version, App, Arg, ArgMatches};
+use failure::{format_err, ResultExt};
+use toml::Value;
+
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Target kinds with their inferred directories, see the "Target auto-discovery" section of the Cargo book.
+const TARGET_KINDS: &[(&str, &str)] = &[
+    ("bin", "src/bin"),
+    ("example", "examples"),
+    ("test", "tests"),
+    ("bench", "benches"),
+];
+
+fn main() {
+    let matches = get_cli_app().get_matches();
+
+    if let Err(error) = run(&matches) {
+        cargo::handle_error(&error, &mut Shell::new());
+        exit(1);
+    }
+}
+
+fn get_cli_app() -> App<'static, 'static> {
+    App::new("cargo-stub-targets")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(
+            "Creates empty sources of the workspace targets, so Cargo can load the manifests alone",
+        )
+        .arg(
+            Arg::with_name("manifest_path")
+                .long("manifest-path")
+                .takes_value(true)
+                .value_name("PATH")
+                .required(true)
+                .help("Path to Cargo.toml"),
+        )
+}
+
+fn run(matches: &ArgMatches<'static>) -> CargoResult<()> {
+    let manifest_path = PathBuf::from(matches.value_of("manifest_path").unwrap());
+
+    for (path, manifest) in load_workspace(&manifest_path)? {
+        let dir = path.parent().unwrap();
+
+        for target in stub_targets(&manifest) {
+            create_stub(&dir.join(target))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the manifests Cargo would load: the workspace root, its members and their path dependencies.
+fn load_workspace(manifest_path: &Path) -> CargoResult<Vec<(PathBuf, Value)>> {
+    let mut pending: VecDeque<_> = vec![manifest_path.to_owned()].into();
+    let mut visited = BTreeSet::new();
+    let mut manifests = vec![];
+
+    if let Some(root) = find_workspace_root(manifest_path)? {
+        pending.push_back(root);
+    }
+
+    while let Some(path) = pending.pop_front() {
+        let path = normalize(&path);
+
+        if !visited.insert(path.clone()) {
+            continue;
+        }
+
+        let manifest = read_manifest(&path)?;
+        let dir = path.parent().unwrap();
+
+        for linked in linked_manifests(dir, &manifest)? {
+            pending.push_back(linked);
+        }
+
+        manifests.push((path, manifest));
+    }
+
+    Ok(manifests)
+}
+
+/// Workspace root of the manifest, either explicitly specified or found in the parent directories.
+fn find_workspace_root(manifest_path: &Path) -> CargoResult<Option<PathBuf>> {
+    let manifest = read_manifest(manifest_path)?;
+    let dir = manifest_path.parent().unwrap();
+
+    if manifest.get("workspace").is_some() {
+        return Ok(None);
+    }
+
+    let explicit = {
+        manifest
+            .get("package")
+            .and_then(|package| package.get("workspace"))
+            .and_then(Value::as_str)
+    };
+
+    if let Some(root) = explicit {
+        return Ok(Some(dir.join(root).join("Cargo.toml")));
+    }
+
+    for parent in normalize(dir).ancestors().skip(1) {
+        let candidate = parent.join("Cargo.toml");
+
+        if candidate.exists() && read_manifest(&candidate)?.get("workspace").is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+fn read_manifest(path: &Path) -> CargoResult<Value> {
+    let contents = {
+        fs::read_to_string(path).with_context(|_| format!("Unable to read '{}'", path.display()))?
+    };
+
+    Ok(toml::from_str(&contents)
+        .with_context(|_| format!("Unable to parse '{}'", path.display()))?)
+}
+
+/// Manifests of the workspace members and path dependencies.
+fn linked_manifests(dir: &Path, manifest: &Value) -> CargoResult<Vec<PathBuf>> {
+    let mut manifests = vec![];
+
+    if let Some(workspace) = manifest.get("workspace") {
+        let excluded: Vec<_> = {
+            string_list(workspace.get("exclude"))
+                .map(|path| normalize(&dir.join(path)))
+                .collect()
+        };
+
+        for pattern in string_list(workspace.get("members")) {
+            let pattern = dir.join(pattern);
+            let pattern = pattern.to_string_lossy();
+
+            for entry in glob::glob(&pattern).context("Invalid workspace member pattern")? {
+                let member = normalize(&entry?);
+
+                if member.join("Cargo.toml").exists() && !excluded.contains(&member) {
+                    manifests.push(member.join("Cargo.toml"));
+                }
+            }
+        }
+    }
+
+    manifests.extend(
+        path_dependencies(manifest)
+            .into_iter()
+            .map(|path| dir.join(path).join("Cargo.toml")),
+    );
+
+    Ok(manifests)
+}
+
+/// Paths of the dependencies (including target-specific ones), patches and replacements.
+fn path_dependencies(manifest: &Value) -> Vec<PathBuf> {
+    let targets = {
+        manifest
+            .get("target")
+            .and_then(Value::as_table)
+            .into_iter()
+            .flat_map(|targets| targets.values())
+    };
+
+    let dependency_tables = {
+        Some(manifest).into_iter().chain(targets).flat_map(|table| {
+            DEPENDENCY_TABLES
+                .iter()
+                .filter_map(move |name| table.get(name))
+        })
+    };
+
+    let patches = {
+        manifest
+            .get("patch")
+            .and_then(Value::as_table)
+            .into_iter()
+            .flat_map(|registries| registries.values())
+    };
+
+    dependency_tables
+        .chain(patches)
+        .chain(manifest.get("replace"))
+        .filter_map(Value::as_table)
+        .flat_map(|dependencies| dependencies.values())
+        .filter_map(|dependency| dependency.get("path")?.as_str())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Relative paths of the declared targets, and the ones Cargo would infer for them.
+fn stub_targets(manifest: &Value) -> BTreeSet<PathBuf> {
+    let package = match manifest.get("package") {
+        Some(package) => package,
+        None => return BTreeSet::new(),
+    };
+
+    let package_name = package.get("name").and_then(Value::as_str);
+    let mut targets = BTreeSet::new();
+
+    // The library makes sure the package has at least one target.
+    targets.insert(target_path(manifest.get("lib"), "src/lib.rs".into()));
+
+    for (kind, dir) in TARGET_KINDS {
+        let declared = {
+            manifest
+                .get(kind)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+        };
+
+        for target in declared {
+            let name = target
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let inferred = if *kind == "bin" && Some(name) == package_name {
+                PathBuf::from("src/main.rs")
+            } else {
+                Path::new(dir).join(format!("{}.rs", name))
+            };
+
+            targets.insert(target_path(Some(target), inferred));
+        }
+    }
+
+    match package.get("build") {
+        Some(Value::String(path)) => {
+            targets.insert(path.into());
+        }
+
+        Some(Value::Boolean(true)) => {
+            targets.insert("build.rs".into());
+        }
+
+        _ => {}
+    }
+
+    targets
+}
+
+fn target_path(target: Option<&Value>, inferred: PathBuf) -> PathBuf {
+    target
+        .and_then(|target| target.get("path"))
+        .and_then(Value::as_str)
+        .map(PathBuf::from)
+        .unwrap_or(inferred)
+}
+
+fn string_list(value: Option<&Value>) -> impl Iterator<Item = &str> {
+    value
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+}
+
+fn create_stub(path: &Path) -> CargoResult<()> {
+    if path.exists() {
+        return Ok(());
+    }
+
+    let parent = {
+        path.parent()
+            .ok_or_else(|| format_err!("Invalid target path '{}'", path.display()))?
+    };
+
+    fs::create_dir_all(parent)
+        .with_context(|_| format!("Unable to create '{}'", parent.display()))?;
+
+    fs::write(path, "").with_context(|_| format!("Unable to create '{}'", path.display()))?;
+
+    Ok(())
+}
+
+/// Lexically resolves `.` and `..` components, as the directories of the manifests don't have to exist yet.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component.as_os_str().to_str() {
+            Some(".") => {}
+            Some("..") => {
+                normalized.pop();
+            }
+
+            _ => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declared_and_inferred_targets() {
+        let manifest: Value = toml::from_str(
+            r#"
+            [package]
+            name = "multi-bin"
+            version = "0.1.0"
+            build = 'scripts/build.rs'
+
+            [lib]
+            path = 'lib/mod.rs'
+
+            [[bin]]
+            name = "multi-bin"
+
+            [[bin]]
+            name = "tool"
+
+            [[bin]]
+            name = "other"
+            path = "src/other/main.rs"
+
+            [[test]]
+            name = "integration"
+
+            [[example]]
+            name = "demo"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            stub_targets(&manifest),
+            vec![
+                "examples/demo.rs",
+                "lib/mod.rs",
+                "scripts/build.rs",
+                "src/bin/tool.rs",
+                "src/main.rs",
+                "src/other/main.rs",
+                "tests/integration.rs",
+            ]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect()
+        );
+    }
+
+    #[test]
+    fn default_targets() {
+        let manifest: Value = toml::from_str(
+            r#"
+            [package]
+            name = "single"
+            version = "0.1.0"
+            build = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            stub_targets(&manifest),
+            vec!["build.rs", "src/lib.rs"]
+                .into_iter()
+                .map(PathBuf::from)
+                .collect()
+        );
+
+        let workspace: Value = toml::from_str("[workspace]\nmembers = ['a']").unwrap();
+
+        assert!(stub_targets(&workspace).is_empty());
+    }
+
+    #[test]
+    fn dependencies_paths() {
+        let manifest: Value = toml::from_str(
+            r#"
+            [package]
+            name = "single"
+            version = "0.1.0"
+
+            [dependencies]
+            local = { path = "../local" }
+            remote = "1.0"
+
+            [dependencies.other]
+            path = 'vendor/other'
+
+            [build-dependencies]
+            codegen = { path = "codegen", version = "0.1" }
+
+            [target.'cfg(unix)'.dev-dependencies]
+            unix-only = { path = "../unix-only" }
+
+            [patch.crates-io]
+            patched = { path = "patches/patched" }
+            "#,
+        )
+        .unwrap();
+
+        let mut paths = path_dependencies(&manifest);
+        paths.sort();
+
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("../local"),
+                PathBuf::from("../unix-only"),
+                PathBuf::from("codegen"),
+                PathBuf::from("patches/patched"),
+                PathBuf::from("vendor/other"),
+            ]
+        );
+    }
+
+    #[test]
+    fn paths_normalization() {
+        assert_eq!(
+            normalize(Path::new("/context/crates/a/../../b/./Cargo.toml")),
+            PathBuf::from("/context/b/Cargo.toml")
+        );
+    }
+}
@@ -14,6 +14,19 @@ pub enum Origin {
 pub struct Metadata {
     pub origin: Origin,
     pub metadata: Option<Value>,
+    pub package: Option<PackageDetails>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PackageDetails {
+    pub name: String,
+    pub version: Version,
+    pub description: Option<String>,
+    pub authors: Vec<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub documentation: Option<String>,
 }
 
 pub mod manifest {
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use failure::{bail, format_err, Error};
 use serde::{Deserialize, Serialize};
@@ -16,6 +17,12 @@ pub struct BaseConfig {
     pub builder: BaseBuilderConfig,
     pub output: BaseOutputConfig,
     pub binaries: Vec<BinaryDefinition>,
+
+    /// Details of the package which defines the output image.
+    pub package: Option<PackageDetails>,
+
+    pub audit: Option<AuditConfig>,
+    pub context: Option<BaseContextConfig>,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
@@ -26,6 +33,8 @@ pub struct BaseBuilderConfig {
     pub env: Option<BTreeMap<String, String>>,
     pub target: Option<String>,
     pub setup_commands: Option<Vec<CustomCommand>>,
+    pub locked: Option<bool>,
+    pub frozen: Option<bool>,
 }
 
 #[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
@@ -43,12 +52,109 @@ pub struct BaseOutputConfig {
     pub stop_signal: Option<Signal>,
     pub pre_install_commands: Option<Vec<CustomCommand>>,
     pub post_install_commands: Option<Vec<CustomCommand>>,
+    pub copy: Option<Vec<CopyDefinition>>,
+    pub from_builder: Option<Vec<CopyDefinition>>,
+    pub bundle_shared_libs: Option<bool>,
+    pub strip: Option<StripKind>,
+    pub split_debuginfo: Option<bool>,
+    pub healthcheck: Option<HealthcheckDefinition>,
+    pub package_labels: Option<bool>,
+    pub sbom: Option<bool>,
+    pub licenses: Option<LicensesDefinition>,
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
 pub struct BinaryDefinition {
     pub name: String,
     pub destination: PathBuf,
+    pub strip: Option<StripKind>,
+    pub split_debuginfo: Option<bool>,
+    pub transform: Option<Vec<CustomCommand>>,
+
+    #[serde(flatten)]
+    pub permissions: FilePermissions,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct CopyDefinition {
+    pub from: PathBuf,
+    pub to: PathBuf,
+
+    #[serde(flatten)]
+    pub permissions: FilePermissions,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq)]
+pub struct FilePermissions {
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct PackageDetails {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub authors: Vec<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+    pub repository: Option<String>,
+    pub documentation: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct HealthcheckDefinition {
+    #[serde(flatten)]
+    pub test: CustomCommandKind,
+
+    pub interval: Option<HealthcheckDuration>,
+    pub timeout: Option<HealthcheckDuration>,
+    pub start_period: Option<HealthcheckDuration>,
+    pub retries: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct BaseContextConfig {
+    pub exclude: Option<Vec<String>>,
+    pub workspace_build_scripts: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditConfig {
+    pub database: Option<PathBuf>,
+    pub mode: Option<AuditMode>,
+    pub ignore: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditMode {
+    Deny,
+    Warn,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct LicensesDefinition {
+    pub destination: Option<PathBuf>,
+    pub deny: Option<Vec<String>>,
+}
+
+/// Duration in Docker's notation: `30s`, `1m30s`, `500ms`.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct HealthcheckDuration(pub Duration);
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum StripKind {
+    Symbols,
+    Debuginfo,
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
@@ -70,21 +176,90 @@ impl TryFrom<Vec<schema::MetadataWrapper>> for BaseConfig {
     type Error = Error;
 
     fn try_from(raw: Vec<schema::MetadataWrapper>) -> Result<Self, Self::Error> {
-        let (builder, output, binaries) = {
+        let (builder, output, binaries, package, audit, context) = {
             raw.into_iter()
-                .filter_map(|item| item.metadata)
-                .filter_map(|item| item.wharf)
-                .try_fold((None, None, vec![]), extract_config)?
+                .filter_map(|item| Some((item.metadata?.wharf?, item.package)))
+                .try_fold((None, None, vec![], None, None, None), extract_config)?
         };
 
         Ok(Self {
             builder: builder.ok_or_else(|| format_err!("Missing 'wharf.builder' section"))?,
             output: output.ok_or_else(|| format_err!("Missing 'wharf.output' section"))?,
             binaries,
+            package,
+            audit,
+            context,
         })
     }
 }
 
+impl FilePermissions {
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.owner.is_none() && self.group.is_none()
+    }
+}
+
+impl HealthcheckDefinition {
+    pub fn uses_command(&self, path: &str) -> bool {
+        match self.test {
+            CustomCommandKind::Command(ref name_and_args) => {
+                name_and_args.first().map(String::as_str) == Some(path)
+            }
+
+            CustomCommandKind::Shell(ref shell) => {
+                shell.split_whitespace().any(|token| token == path)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for HealthcheckDuration {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let mut total = Duration::default();
+        let mut rest = value.as_str();
+
+        if rest.is_empty() {
+            bail!("Empty duration");
+        }
+
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let amount: u64 = match rest[..digits].parse() {
+                Ok(amount) => amount,
+                Err(_) => bail!("Invalid duration: '{}'", value),
+            };
+
+            rest = &rest[digits..];
+
+            let unit = rest
+                .find(|c: char| c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            total += match &rest[..unit] {
+                "ms" => Duration::from_millis(amount),
+                "s" => Duration::from_secs(amount),
+                "m" => Duration::from_secs(amount * 60),
+                "h" => Duration::from_secs(amount * 60 * 60),
+
+                other => bail!("Unknown duration unit '{}' in '{}'", other, value),
+            };
+
+            rest = &rest[unit..];
+        }
+
+        Ok(HealthcheckDuration(total))
+    }
+}
+
+impl From<HealthcheckDuration> for String {
+    fn from(duration: HealthcheckDuration) -> Self {
+        format!("{}ms", duration.0.as_millis())
+    }
+}
+
 impl BaseBuilderConfig {
     pub fn source(&self) -> ImageSource {
         Source::image(&self.image).with_resolve_mode(ResolveMode::PreferLocal)
@@ -97,6 +272,18 @@ impl BaseOutputConfig {
     }
 }
 
+impl CustomCommand {
+    pub fn display_text(&self) -> Cow<str> {
+        match (&self.display, &self.kind) {
+            (Some(display), _) => Cow::Borrowed(display.as_str()),
+            (None, CustomCommandKind::Command(name_and_args)) => {
+                Cow::Owned(name_and_args.join(" "))
+            }
+            (None, CustomCommandKind::Shell(shell)) => Cow::Borrowed(shell.as_str()),
+        }
+    }
+}
+
 impl<'a> From<&'a CustomCommand> for (&'a str, Vec<&'a str>, Cow<'a, str>) {
     fn from(command: &'a CustomCommand) -> Self {
         match command.kind {
@@ -106,21 +293,13 @@ impl<'a> From<&'a CustomCommand> for (&'a str, Vec<&'a str>, Cow<'a, str>) {
                     .iter()
                     .map(String::as_str)
                     .collect::<Vec<_>>(),
-                command
-                    .display
-                    .as_ref()
-                    .map(|display| Cow::Borrowed(display.as_str()))
-                    .unwrap_or_else(|| Cow::Owned(name_and_args.join(" "))),
+                command.display_text(),
             ),
 
             CustomCommandKind::Shell(ref shell) => (
                 "/bin/sh",
                 vec!["-c", shell.as_str()],
-                command
-                    .display
-                    .as_ref()
-                    .map(|display| Cow::Borrowed(display.as_str()))
-                    .unwrap_or_else(|| Cow::Borrowed(shell.as_str())),
+                command.display_text(),
             ),
         }
     }
@@ -130,10 +309,16 @@ type ConfigCtx = (
     Option<BaseBuilderConfig>,
     Option<BaseOutputConfig>,
     Vec<BinaryDefinition>,
+    Option<PackageDetails>,
+    Option<AuditConfig>,
+    Option<BaseContextConfig>,
 );
 
-fn extract_config(cx: ConfigCtx, metadata: schema::WharfMetadata) -> Result<ConfigCtx, Error> {
-    let (mut builder, mut output, mut binaries) = cx;
+type MetadataCtx = (schema::WharfMetadata, Option<PackageDetails>);
+
+fn extract_config(cx: ConfigCtx, metadata: MetadataCtx) -> Result<ConfigCtx, Error> {
+    let (mut builder, mut output, mut binaries, mut package, mut audit, mut context) = cx;
+    let (metadata, incoming_package) = metadata;
 
     if let Some(mut incoming) = metadata.binary {
         binaries.append(&mut incoming);
@@ -152,14 +337,37 @@ fn extract_config(cx: ConfigCtx, metadata: schema::WharfMetadata) -> Result<Conf
     output = match (output.take(), metadata.output) {
         (output, None) => output,
 
-        (None, Some(incoming)) => Some(incoming),
+        (None, Some(incoming)) => {
+            package = incoming_package;
+            Some(incoming)
+        }
 
         (Some(_), Some(_)) => {
             bail!("Found duplicated 'wharf.output' section");
         }
     };
 
-    Ok((builder, output, binaries))
+    audit = match (audit.take(), metadata.audit) {
+        (audit, None) => audit,
+
+        (None, Some(incoming)) => Some(incoming),
+
+        (Some(_), Some(_)) => {
+            bail!("Found duplicated 'wharf.audit' section");
+        }
+    };
+
+    context = match (context.take(), metadata.context) {
+        (context, None) => context,
+
+        (None, Some(incoming)) => Some(incoming),
+
+        (Some(_), Some(_)) => {
+            bail!("Found duplicated 'wharf.context' section");
+        }
+    };
+
+    Ok((builder, output, binaries, package, audit, context))
 }
 
 #[test]
@@ -168,6 +376,7 @@ fn transformation() {
 
     let raw = vec![
         MetadataWrapper {
+            package: Some(mocked_package("output-package")),
             metadata: Some(PackageMetadata {
                 wharf: Some(WharfMetadata {
                     output: Some(BaseOutputConfig {
@@ -183,14 +392,26 @@ fn transformation() {
                         stop_signal: None,
                         pre_install_commands: None,
                         post_install_commands: None,
+                        copy: None,
+                        from_builder: None,
+                        bundle_shared_libs: None,
+                        strip: None,
+                        split_debuginfo: None,
+                        healthcheck: None,
+                        package_labels: None,
+                        sbom: None,
+                        licenses: None,
                     }),
 
                     builder: None,
                     binary: None,
+                    audit: None,
+                    context: None,
                 }),
             }),
         },
         MetadataWrapper {
+            package: Some(mocked_package("builder-package")),
             metadata: Some(PackageMetadata {
                 wharf: Some(WharfMetadata {
                     builder: Some(BaseBuilderConfig {
@@ -199,37 +420,62 @@ fn transformation() {
                         user: None,
                         target: None,
                         setup_commands: None,
+                        locked: None,
+                        frozen: None,
                     }),
 
                     output: None,
                     binary: None,
+                    audit: None,
+                    context: None,
                 }),
             }),
         },
         MetadataWrapper {
+            package: None,
             metadata: Some(PackageMetadata {
                 wharf: Some(WharfMetadata {
                     binary: Some(vec![BinaryDefinition {
                         name: "binary-1".into(),
                         destination: "/bin/binary-1".into(),
+                        strip: None,
+                        split_debuginfo: None,
+                        transform: None,
+                        permissions: Default::default(),
                     }]),
 
                     output: None,
                     builder: None,
+                    audit: None,
+                    context: None,
                 }),
             }),
         },
-        MetadataWrapper { metadata: None },
         MetadataWrapper {
+            metadata: None,
+            package: None,
+        },
+        MetadataWrapper {
+            package: None,
             metadata: Some(PackageMetadata {
                 wharf: Some(WharfMetadata {
                     binary: Some(vec![BinaryDefinition {
                         name: "binary-2".into(),
                         destination: "/usr/local/bin/binary-2".into(),
+                        strip: None,
+                        split_debuginfo: None,
+                        transform: None,
+                        permissions: Default::default(),
                     }]),
 
                     output: None,
                     builder: None,
+                    audit: Some(AuditConfig {
+                        database: Some("advisory-db".into()),
+                        mode: Some(AuditMode::Warn),
+                        ignore: None,
+                    }),
+                    context: None,
                 }),
             }),
         },
@@ -244,6 +490,8 @@ fn transformation() {
                 user: None,
                 target: None,
                 setup_commands: None,
+                locked: None,
+                frozen: None,
             },
             output: BaseOutputConfig {
                 image: "alpine:latest".into(),
@@ -258,27 +506,109 @@ fn transformation() {
                 stop_signal: None,
                 pre_install_commands: None,
                 post_install_commands: None,
+                copy: None,
+                from_builder: None,
+                bundle_shared_libs: None,
+                strip: None,
+                split_debuginfo: None,
+                healthcheck: None,
+                package_labels: None,
+                sbom: None,
+                licenses: None,
             },
             binaries: vec![
                 BinaryDefinition {
                     name: "binary-1".into(),
                     destination: "/bin/binary-1".into(),
+                    strip: None,
+                    split_debuginfo: None,
+                    transform: None,
+                    permissions: Default::default(),
                 },
                 BinaryDefinition {
                     name: "binary-2".into(),
                     destination: "/usr/local/bin/binary-2".into(),
+                    strip: None,
+                    split_debuginfo: None,
+                    transform: None,
+                    permissions: Default::default(),
                 }
-            ]
+            ],
+            package: Some(mocked_package("output-package")),
+            audit: Some(AuditConfig {
+                database: Some("advisory-db".into()),
+                mode: Some(AuditMode::Warn),
+                ignore: None,
+            }),
+            context: None,
         }
     );
 }
 
+#[cfg(test)]
+fn mocked_package(name: &str) -> PackageDetails {
+    PackageDetails {
+        name: name.into(),
+        version: "0.1.0".into(),
+        description: None,
+        authors: vec![],
+        license: None,
+        homepage: None,
+        repository: None,
+        documentation: None,
+    }
+}
+
+#[test]
+fn healthcheck_command_detection() {
+    let healthcheck = |test| HealthcheckDefinition {
+        test,
+        interval: None,
+        timeout: None,
+        start_period: None,
+        retries: None,
+    };
+
+    let probe = "/usr/local/bin/cargo-healthcheck-probe";
+
+    assert!(
+        healthcheck(CustomCommandKind::Command(vec![probe.into(), "tcp".into()]))
+            .uses_command(probe)
+    );
+    assert!(!healthcheck(CustomCommandKind::Command(vec![])).uses_command(probe));
+
+    assert!(
+        healthcheck(CustomCommandKind::Shell(format!("{} tcp :80", probe))).uses_command(probe)
+    );
+    assert!(
+        !healthcheck(CustomCommandKind::Shell(format!("{}-v2 tcp :80", probe))).uses_command(probe)
+    );
+}
+
+#[test]
+fn healthcheck_durations() {
+    assert_eq!(
+        HealthcheckDuration::try_from(String::from("1m30s")).unwrap(),
+        HealthcheckDuration(Duration::from_secs(90))
+    );
+
+    assert_eq!(
+        HealthcheckDuration::try_from(String::from("500ms")).unwrap(),
+        HealthcheckDuration(Duration::from_millis(500))
+    );
+
+    assert!(HealthcheckDuration::try_from(String::from("")).is_err());
+    assert!(HealthcheckDuration::try_from(String::from("10")).is_err());
+    assert!(HealthcheckDuration::try_from(String::from("5d")).is_err());
+}
+
 #[test]
 fn duplicated_config() {
     use schema::*;
 
     let raw = vec![
         MetadataWrapper {
+            package: None,
             metadata: Some(PackageMetadata {
                 wharf: Some(WharfMetadata {
                     builder: Some(BaseBuilderConfig {
@@ -287,6 +617,8 @@ fn duplicated_config() {
                         user: None,
                         target: None,
                         setup_commands: None,
+                        locked: None,
+                        frozen: None,
                     }),
                     output: Some(BaseOutputConfig {
                         image: "alpine:latest".into(),
@@ -301,13 +633,25 @@ fn duplicated_config() {
                         stop_signal: None,
                         pre_install_commands: None,
                         post_install_commands: None,
+                        copy: None,
+                        from_builder: None,
+                        bundle_shared_libs: None,
+                        strip: None,
+                        split_debuginfo: None,
+                        healthcheck: None,
+                        package_labels: None,
+                        sbom: None,
+                        licenses: None,
                     }),
 
                     binary: None,
+                    audit: None,
+                    context: None,
                 }),
             }),
         },
         MetadataWrapper {
+            package: None,
             metadata: Some(PackageMetadata {
                 wharf: Some(WharfMetadata {
                     builder: Some(BaseBuilderConfig {
@@ -316,10 +660,14 @@ fn duplicated_config() {
                         user: None,
                         target: None,
                         setup_commands: None,
+                        locked: None,
+                        frozen: None,
                     }),
 
                     output: None,
                     binary: None,
+                    audit: None,
+                    context: None,
                 }),
             }),
         },
@@ -329,6 +677,7 @@ fn duplicated_config() {
 
     let raw = vec![
         MetadataWrapper {
+            package: None,
             metadata: Some(PackageMetadata {
                 wharf: Some(WharfMetadata {
                     builder: Some(BaseBuilderConfig {
@@ -337,6 +686,8 @@ fn duplicated_config() {
                         user: None,
                         target: None,
                         setup_commands: None,
+                        locked: None,
+                        frozen: None,
                     }),
                     output: Some(BaseOutputConfig {
                         image: "alpine:latest".into(),
@@ -351,13 +702,25 @@ fn duplicated_config() {
                         stop_signal: None,
                         pre_install_commands: None,
                         post_install_commands: None,
+                        copy: None,
+                        from_builder: None,
+                        bundle_shared_libs: None,
+                        strip: None,
+                        split_debuginfo: None,
+                        healthcheck: None,
+                        package_labels: None,
+                        sbom: None,
+                        licenses: None,
                     }),
 
                     binary: None,
+                    audit: None,
+                    context: None,
                 }),
             }),
         },
         MetadataWrapper {
+            package: None,
             metadata: Some(PackageMetadata {
                 wharf: Some(WharfMetadata {
                     output: Some(BaseOutputConfig {
@@ -373,10 +736,21 @@ fn duplicated_config() {
                         stop_signal: None,
                         pre_install_commands: None,
                         post_install_commands: None,
+                        copy: None,
+                        from_builder: None,
+                        bundle_shared_libs: None,
+                        strip: None,
+                        split_debuginfo: None,
+                        healthcheck: None,
+                        package_labels: None,
+                        sbom: None,
+                        licenses: None,
                     }),
 
                     builder: None,
                     binary: None,
+                    audit: None,
+                    context: None,
                 }),
             }),
         },
@@ -390,12 +764,14 @@ fn missing_config() {
     use schema::*;
 
     let raw = vec![MetadataWrapper {
+        package: None,
         metadata: Some(PackageMetadata { wharf: None }),
     }];
 
     assert!(BaseConfig::try_from(raw).is_err());
 
     let raw = vec![MetadataWrapper {
+        package: None,
         metadata: Some(PackageMetadata {
             wharf: Some(WharfMetadata {
                 builder: Some(BaseBuilderConfig {
@@ -404,10 +780,14 @@ fn missing_config() {
                     user: None,
                     target: None,
                     setup_commands: None,
+                    locked: None,
+                    frozen: None,
                 }),
 
                 output: None,
                 binary: None,
+                audit: None,
+                context: None,
             }),
         }),
     }];
@@ -415,6 +795,7 @@ fn missing_config() {
     assert!(BaseConfig::try_from(raw).is_err());
 
     let raw = vec![MetadataWrapper {
+        package: None,
         metadata: Some(PackageMetadata {
             wharf: Some(WharfMetadata {
                 output: Some(BaseOutputConfig {
@@ -430,10 +811,21 @@ fn missing_config() {
                     stop_signal: None,
                     pre_install_commands: None,
                     post_install_commands: None,
+                    copy: None,
+                    from_builder: None,
+                    bundle_shared_libs: None,
+                    strip: None,
+                    split_debuginfo: None,
+                    healthcheck: None,
+                    package_labels: None,
+                    sbom: None,
+                    licenses: None,
                 }),
 
                 builder: None,
                 binary: None,
+                audit: None,
+                context: None,
             }),
         }),
     }];
@@ -446,6 +838,7 @@ mod schema {
 
     #[derive(Debug, Deserialize)]
     pub(super) struct MetadataWrapper {
+        pub(super) package: Option<PackageDetails>,
         pub(super) metadata: Option<PackageMetadata>,
     }
 
@@ -459,5 +852,7 @@ mod schema {
         pub(super) builder: Option<BaseBuilderConfig>,
         pub(super) output: Option<BaseOutputConfig>,
         pub(super) binary: Option<Vec<BinaryDefinition>>,
+        pub(super) audit: Option<AuditConfig>,
+        pub(super) context: Option<BaseContextConfig>,
     }
 }
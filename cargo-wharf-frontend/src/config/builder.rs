@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use failure::{format_err, Error, ResultExt};
 use log::*;
@@ -17,7 +18,7 @@ use crate::shared::TARGET_PATH;
 #[derive(Debug, Serialize)]
 pub struct BuilderConfig {
     #[serde(skip_serializing)]
-    source: ImageSource,
+    source: Arc<ImageSource>,
 
     overrides: BaseBuilderConfig,
     defaults: BuilderConfigDefaults,
@@ -50,11 +51,11 @@ impl BuilderConfig {
                 .ok_or_else(|| format_err!("Missing source image config"))?
         };
 
-        let source = if !digest.is_empty() {
+        let source = Arc::new(if !digest.is_empty() {
             source.with_digest(digest)
         } else {
             source
-        };
+        });
 
         let merged_env = merge_spec_and_overriden_env(&spec.env, &config.env);
         let user = {
@@ -86,7 +87,7 @@ impl BuilderConfig {
     #[cfg(test)]
     pub fn mocked_new(source: ImageSource, cargo_home: PathBuf) -> Self {
         BuilderConfig {
-            source,
+            source: Arc::new(source),
 
             defaults: Default::default(),
             overrides: Default::default(),
@@ -100,7 +101,7 @@ impl BuilderConfig {
         &self.cargo_home
     }
 
-    pub fn source(&self) -> &ImageSource {
+    pub fn source(&self) -> &Arc<ImageSource> {
         &self.source
     }
 
@@ -108,6 +109,14 @@ impl BuilderConfig {
         self.overrides.target.as_ref().map(String::as_str)
     }
 
+    pub fn locked(&self) -> Option<bool> {
+        self.overrides.locked
+    }
+
+    pub fn frozen(&self) -> Option<bool> {
+        self.overrides.frozen
+    }
+
     pub fn user(&self) -> Option<&str> {
         self.overrides
             .user
@@ -139,14 +148,11 @@ impl BaseImageConfig for BuilderConfig {
             command = command.env(name, value);
         }
 
-        command
-            .env("CARGO_HOME", self.cargo_home().display().to_string())
-            .mount(Mount::SharedCache(self.cargo_home().join("git")))
-            .mount(Mount::SharedCache(self.cargo_home().join("registry")))
+        command.env("CARGO_HOME", self.cargo_home().display().to_string())
     }
 
     fn image_source(&self) -> Option<&ImageSource> {
-        Some(&self.source)
+        Some(self.source.as_ref())
     }
 }
 
@@ -0,0 +1,125 @@
+use std::collections::BTreeSet;
+use std::iter::once;
+use std::str::from_utf8;
+
+use failure::{Error, ResultExt};
+use serde::Serialize;
+
+use buildkit_frontend::Bridge;
+use buildkit_llb::ops::source::LocalSource;
+use buildkit_llb::prelude::*;
+
+const DEFAULT_EXCLUDE: &str = "**/target";
+const IGNORE_FILES: &[&str] = &[".dockerignore", "wharf.ignore"];
+
+#[derive(Debug, Serialize)]
+pub struct ContextConfig {
+    exclude: Vec<String>,
+    workspace_build_scripts: BTreeSet<String>,
+
+    #[serde(skip)]
+    source: LocalSource,
+}
+
+impl ContextConfig {
+    pub fn new(exclude: Vec<String>, workspace_build_scripts: BTreeSet<String>) -> Self {
+        let source = {
+            exclude_patterns(
+                Source::local("context").custom_name("Using build context"),
+                &exclude,
+            )
+        };
+
+        Self {
+            exclude,
+            workspace_build_scripts,
+            source,
+        }
+    }
+
+    /// Reads exclude patterns from `.dockerignore` and `wharf.ignore` in the root of the build context.
+    pub async fn read_ignore_files(bridge: &mut Bridge) -> Result<Vec<String>, Error> {
+        let source = {
+            IGNORE_FILES.iter().fold(
+                Source::local("context").custom_name("Reading ignore files"),
+                |source, name| source.add_include_pattern(*name),
+            )
+        };
+
+        let layer = {
+            bridge
+                .solve(Terminal::with(source.output()))
+                .await
+                .context("Unable to read ignore files")?
+        };
+
+        let mut patterns = vec![];
+
+        for name in IGNORE_FILES {
+            if let Ok(contents) = bridge.read_file(&layer, name, None).await {
+                let contents = from_utf8(&contents).context("Invalid ignore file")?;
+
+                patterns.extend(parse_ignore_file(contents));
+            }
+        }
+
+        Ok(patterns)
+    }
+
+    pub fn source(&self) -> &LocalSource {
+        &self.source
+    }
+
+    /// Exclude patterns of the build context, including the default ones.
+    pub fn exclude(&self) -> impl Iterator<Item = &str> {
+        once(DEFAULT_EXCLUDE).chain(self.exclude.iter().map(String::as_str))
+    }
+
+    /// Whether the build script of the package was opted in to read the whole build context.
+    pub fn has_workspace_build_script(&self, package_name: &str) -> bool {
+        self.workspace_build_scripts.contains(package_name)
+    }
+
+    /// Applies the exclude patterns to another source reading the build context.
+    pub fn apply(&self, source: LocalSource) -> LocalSource {
+        exclude_patterns(source, &self.exclude)
+    }
+}
+
+pub fn exclude_patterns(source: LocalSource, patterns: &[String]) -> LocalSource {
+    once(DEFAULT_EXCLUDE)
+        .chain(patterns.iter().map(String::as_str))
+        .fold(source, |source, pattern| {
+            source.add_exclude_pattern(pattern)
+        })
+}
+
+fn parse_ignore_file(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.strip_prefix('!') {
+            Some(pattern) => format!("!{}", pattern.trim_start_matches('/')),
+            None => line.trim_start_matches('/').into(),
+        })
+        .collect()
+}
+
+#[test]
+fn ignore_file_parsing() {
+    let contents = r#"
+        # Build artifacts
+        /target
+        **/*.log
+
+        docs/
+        !docs/README.md
+        !/assets
+    "#;
+
+    assert_eq!(
+        parse_ignore_file(contents),
+        vec!["target", "**/*.log", "docs/", "!docs/README.md", "!assets"]
+    );
+}
@@ -1,23 +1,36 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
 
-use failure::{Error, ResultExt};
+use chrono::prelude::*;
+use failure::{format_err, Error, ResultExt};
+use log::*;
 use serde::Serialize;
 
 use buildkit_frontend::Bridge;
 use buildkit_llb::ops::source::ImageSource;
 use buildkit_llb::prelude::*;
 
+use crate::lockfile::Lockfile;
+use crate::plan::fetch_dependencies;
 use crate::query::Profile;
-use crate::shared::{tools, DOCKERFILE, DOCKERFILE_PATH};
+use crate::shared::{tools, DOCKERFILE_PATH};
 
 mod base;
 mod builder;
+mod context;
 mod output;
+mod vcs;
+mod vendor;
 
-pub use self::base::{BaseConfig, BinaryDefinition, CustomCommand, CustomCommandKind};
+pub use self::base::{
+    AuditConfig, AuditMode, BaseConfig, BinaryDefinition, CustomCommand, CustomCommandKind,
+    FilePermissions, HealthcheckDefinition, HealthcheckDuration, StripKind,
+};
 pub use self::builder::BuilderConfig;
+pub use self::context::ContextConfig;
 pub use self::output::OutputConfig;
+pub use self::vcs::VcsInfo;
+pub use self::vendor::VendorConfig;
 pub use crate::frontend::Options;
 
 const OUTPUT_LAYER_PATH: &str = "/output";
@@ -27,13 +40,28 @@ const OUTPUT_NAME: &str = "build-config.json";
 pub struct Config {
     builder: BuilderConfig,
     output: OutputConfig,
+    context: ContextConfig,
+    vendor: VendorConfig,
     profile: Profile,
     manifest_path: PathBuf,
 
     default_features: bool,
     enabled_features: Vec<String>,
+    export_debuginfo: bool,
+    source_date_epoch: Option<i64>,
+    local_registry: bool,
+    offline: bool,
+    locked: bool,
+    frozen: bool,
 
     binaries: Vec<BinaryDefinition>,
+    audit: Option<AuditConfig>,
+
+    #[serde(skip)]
+    lockfile: Option<Lockfile>,
+
+    #[serde(skip)]
+    fetched_dependencies: Vec<(OperationOutput<'static>, PathBuf)>,
 }
 
 pub trait BaseImageConfig {
@@ -63,12 +91,25 @@ impl Config {
                 .into(),
         ];
 
+        let ignored = {
+            ContextConfig::read_ignore_files(bridge)
+                .await
+                .context("Unable to read ignore files")?
+        };
+
+        let dockerfile = {
+            context::exclude_patterns(
+                Source::local("dockerfile").custom_name("Using build context"),
+                &ignored,
+            )
+        };
+
         let command = {
             Command::run(tools::METADATA_COLLECTOR)
                 .args(args)
                 .cwd(DOCKERFILE_PATH)
                 .mount(Mount::Layer(OutputIdx(0), tools::IMAGE.output(), "/"))
-                .mount(Mount::ReadOnlyLayer(DOCKERFILE.output(), DOCKERFILE_PATH))
+                .mount(Mount::ReadOnlyLayer(dockerfile.output(), DOCKERFILE_PATH))
                 .mount(Mount::Scratch(OutputIdx(1), OUTPUT_LAYER_PATH))
                 .custom_name("Collecting configuration metadata")
         };
@@ -91,14 +132,60 @@ impl Config {
             serde_json::from_slice(&metadata).context("Unable to parse configuration metadata")?
         };
 
+        let context = {
+            let mut exclude = ignored;
+            let mut workspace_build_scripts = BTreeSet::new();
+
+            if let Some(incoming) = base.context {
+                exclude.extend(incoming.exclude.unwrap_or_default());
+                workspace_build_scripts
+                    .extend(incoming.workspace_build_scripts.unwrap_or_default());
+            }
+
+            ContextConfig::new(exclude, workspace_build_scripts)
+        };
+
+        let vendor = {
+            VendorConfig::analyse(bridge, &context)
+                .await
+                .context("Unable to analyse vendored dependencies")?
+        };
+
         let builder = {
             BuilderConfig::analyse(bridge, base.builder)
                 .await
                 .context("Unable to analyse builder image")?
         };
 
+        let locked = options.locked.or_else(|| builder.locked()).unwrap_or(true);
+        let frozen = options.frozen.or_else(|| builder.frozen()).unwrap_or(false);
+
+        let vcs = {
+            VcsInfo::analyse(bridge, &builder, &context, options.vcs_ref.as_deref())
+                .await
+                .context("Unable to analyse VCS revision")?
+        };
+
+        let source_date_epoch = if options.reproducible || options.source_date_epoch.is_some() {
+            let commit_time = vcs.as_ref().and_then(|vcs| vcs.commit_time);
+
+            let epoch = {
+                options
+                    .source_date_epoch
+                    .or(commit_time)
+                    .unwrap_or_else(|| {
+                        warn!("unable to find the source date epoch, falling back to zero");
+                        0
+                    })
+            };
+
+            Some(validate_source_date_epoch(epoch)?)
+        } else {
+            None
+        };
+
         let output = {
-            OutputConfig::analyse(bridge, base.output)
+            OutputConfig::analyse(bridge, base.output, base.package, vcs)
                 .await
                 .context("Unable to analyse output image")?
         };
@@ -110,17 +197,47 @@ impl Config {
                 .unwrap_or(metadata_manifest_path)
         };
 
-        Ok(Self {
+        let lockfile = {
+            Lockfile::analyse(bridge, &context, &manifest_path)
+                .await
+                .context("Unable to analyse Cargo.lock")?
+        };
+
+        let offline = options.offline || vendor.is_vendored();
+
+        if !offline {
+            for package in lockfile.iter().flat_map(Lockfile::unfetchable_git_packages) {
+                warn!(
+                    "unable to fetch '{}' from its git remote with BuildKit, falling back to the fetched dependencies",
+                    package.name
+                );
+            }
+        }
+
+        let config = Self {
             builder,
             output,
+            context,
             manifest_path,
+            offline,
+            locked,
+            frozen,
+            vendor,
 
             profile: options.profile,
             default_features: !options.no_default_features,
             enabled_features: options.features.clone(),
+            export_debuginfo: options.export_debuginfo,
+            source_date_epoch,
+            local_registry: options.local_registry,
 
             binaries: base.binaries,
-        })
+            audit: base.audit,
+            lockfile,
+            fetched_dependencies: vec![],
+        };
+
+        Ok(config.with_fetched_dependencies())
     }
 
     #[cfg(test)]
@@ -133,12 +250,54 @@ impl Config {
         Self {
             builder,
             output,
+            context: ContextConfig::new(vec![], Default::default()),
+            vendor: VendorConfig::default(),
             profile,
             binaries,
             manifest_path: PathBuf::from("Cargo.toml"),
             default_features: false,
             enabled_features: vec![],
+            export_debuginfo: false,
+            source_date_epoch: None,
+            local_registry: false,
+            offline: false,
+            locked: true,
+            frozen: false,
+            audit: None,
+            lockfile: None,
+            fetched_dependencies: vec![],
+        }
+        .with_fetched_dependencies()
+    }
+
+    #[cfg(test)]
+    pub fn with_source_date_epoch(mut self, epoch: i64) -> Self {
+        self.source_date_epoch = Some(epoch);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_workspace_build_scripts(mut self, packages: Vec<String>) -> Self {
+        self.context = ContextConfig::new(vec![], packages.into_iter().collect());
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_local_registry(mut self, lockfile: Lockfile) -> Self {
+        self.local_registry = true;
+        self.offline = true;
+        self.fetched_dependencies = vec![];
+        self.lockfile = Some(lockfile);
+        self
+    }
+
+    /// The dependencies are fetched by a single operation shared by every step that needs them.
+    fn with_fetched_dependencies(mut self) -> Self {
+        if !self.offline {
+            self.fetched_dependencies = fetch_dependencies(&self);
         }
+
+        self
     }
 
     pub fn builder(&self) -> &BuilderConfig {
@@ -149,6 +308,10 @@ impl Config {
         &self.output
     }
 
+    pub fn context(&self) -> &ContextConfig {
+        &self.context
+    }
+
     pub fn find_binary(&self, name: &str) -> Option<&BinaryDefinition> {
         self.binaries.iter().find(|bin| bin.name == name)
     }
@@ -168,6 +331,62 @@ impl Config {
     pub fn manifest_path(&self) -> &Path {
         self.manifest_path.as_path()
     }
+
+    pub fn export_debuginfo(&self) -> bool {
+        self.export_debuginfo
+    }
+
+    /// Timestamp used instead of the current time, when reproducible builds were requested.
+    pub fn source_date_epoch(&self) -> Option<i64> {
+        self.source_date_epoch
+    }
+
+    pub fn local_registry(&self) -> bool {
+        self.local_registry
+    }
+
+    /// Either requested explicitly or implied by vendored dependencies.
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn frozen(&self) -> bool {
+        self.frozen
+    }
+
+    pub fn creation_time(&self) -> DateTime<Utc> {
+        self.source_date_epoch
+            .and_then(|epoch| Utc.timestamp_opt(epoch, 0).single())
+            .unwrap_or_else(Utc::now)
+    }
+
+    pub fn audit(&self) -> Option<&AuditConfig> {
+        self.audit.as_ref()
+    }
+
+    pub fn lockfile(&self) -> Option<&Lockfile> {
+        self.lockfile.as_ref()
+    }
+
+    /// Cargo home directories with the fetched dependencies, empty for offline builds.
+    pub fn fetched_dependencies(&self) -> &[(OperationOutput<'static>, PathBuf)] {
+        &self.fetched_dependencies
+    }
+
+    pub fn binary_strip(&self, binary: &BinaryDefinition) -> Option<StripKind> {
+        binary.strip.or_else(|| self.output.strip())
+    }
+
+    pub fn binary_split_debuginfo(&self, binary: &BinaryDefinition) -> bool {
+        binary
+            .split_debuginfo
+            .or_else(|| self.output.split_debuginfo())
+            .unwrap_or(false)
+    }
 }
 
 fn merge_spec_and_overriden_env(
@@ -183,3 +402,23 @@ fn merge_spec_and_overriden_env(
         (spec, config) => spec.or(config).unwrap_or_default(),
     }
 }
+
+/// The epoch comes from user input and has to be representable as the image creation time.
+fn validate_source_date_epoch(epoch: i64) -> Result<i64, Error> {
+    Utc.timestamp_opt(epoch, 0)
+        .single()
+        .map(|_| epoch)
+        .ok_or_else(|| format_err!("Invalid source date epoch: {}", epoch))
+}
+
+#[test]
+fn source_date_epoch_validation() {
+    assert_eq!(validate_source_date_epoch(0).unwrap(), 0);
+    assert_eq!(
+        validate_source_date_epoch(1_500_000_000).unwrap(),
+        1_500_000_000
+    );
+
+    assert!(validate_source_date_epoch(i64::MAX).is_err());
+    assert!(validate_source_date_epoch(i64::MIN).is_err());
+}
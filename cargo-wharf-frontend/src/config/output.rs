@@ -5,13 +5,16 @@ use failure::{format_err, Error, ResultExt};
 use log::*;
 use serde::Serialize;
 
-use buildkit_frontend::oci::{self, Signal};
+use buildkit_frontend::oci::{self, LayerHistoryItem, Signal};
 use buildkit_frontend::Bridge;
 use buildkit_llb::ops::source::ImageSource;
 use buildkit_llb::prelude::*;
 
-use super::base::{BaseOutputConfig, CustomCommand};
-use super::{merge_spec_and_overriden_env, BaseImageConfig};
+use super::base::{
+    BaseOutputConfig, CopyDefinition, CustomCommand, HealthcheckDefinition, LicensesDefinition,
+    PackageDetails, StripKind,
+};
+use super::{merge_spec_and_overriden_env, BaseImageConfig, VcsInfo};
 
 #[derive(Debug, Serialize)]
 pub struct OutputConfig {
@@ -21,6 +24,9 @@ pub struct OutputConfig {
     overrides: BaseOutputConfig,
     defaults: OutputConfigDefaults,
     merged_env: BTreeMap<String, String>,
+    package: Option<PackageDetails>,
+    vcs: Option<VcsInfo>,
+    history: Vec<LayerHistoryItem>,
 }
 
 #[derive(Debug, Serialize, Default)]
@@ -34,9 +40,19 @@ struct OutputConfigDefaults {
 }
 
 impl OutputConfig {
-    pub async fn analyse(bridge: &mut Bridge, config: BaseOutputConfig) -> Result<Self, Error> {
+    pub async fn analyse(
+        bridge: &mut Bridge,
+        config: BaseOutputConfig,
+        package: Option<PackageDetails>,
+        vcs: Option<VcsInfo>,
+    ) -> Result<Self, Error> {
+        let package = match config.package_labels {
+            Some(true) => package,
+            _ => None,
+        };
+
         if config.image == "scratch" {
-            return Ok(Self::scratch(config));
+            return Ok(Self::scratch(config, package, vcs));
         }
 
         let source = config.source();
@@ -50,6 +66,7 @@ impl OutputConfig {
 
         debug!("resolved output image config: {:#?}", spec.config);
 
+        let history = spec.history.unwrap_or_default();
         let spec = {
             spec.config
                 .ok_or_else(|| format_err!("Missing source image config"))?
@@ -68,15 +85,25 @@ impl OutputConfig {
             overrides: config,
             defaults: spec.into(),
             merged_env,
+            package,
+            vcs,
+            history,
         })
     }
 
-    fn scratch(config: BaseOutputConfig) -> Self {
+    fn scratch(
+        config: BaseOutputConfig,
+        package: Option<PackageDetails>,
+        vcs: Option<VcsInfo>,
+    ) -> Self {
         Self {
             source: None,
             merged_env: config.env.clone().unwrap_or_default(),
             overrides: config,
             defaults: Default::default(),
+            package,
+            vcs,
+            history: vec![],
         }
     }
 
@@ -88,6 +115,9 @@ impl OutputConfig {
             overrides: Default::default(),
             defaults: Default::default(),
             merged_env: Default::default(),
+            package: None,
+            vcs: None,
+            history: vec![],
         }
     }
 
@@ -122,6 +152,78 @@ impl OutputConfig {
     pub fn post_install_commands(&self) -> Option<&Vec<CustomCommand>> {
         self.overrides.post_install_commands.as_ref()
     }
+
+    pub fn copy(&self) -> Option<&Vec<CopyDefinition>> {
+        self.overrides.copy.as_ref()
+    }
+
+    pub fn copy_from_builder(&self) -> Option<&Vec<CopyDefinition>> {
+        self.overrides.from_builder.as_ref()
+    }
+
+    pub fn bundle_shared_libs(&self) -> bool {
+        self.overrides.bundle_shared_libs.unwrap_or(false)
+    }
+
+    pub fn strip(&self) -> Option<StripKind> {
+        self.overrides.strip
+    }
+
+    pub fn split_debuginfo(&self) -> Option<bool> {
+        self.overrides.split_debuginfo
+    }
+
+    pub fn sbom(&self) -> bool {
+        self.overrides.sbom.unwrap_or(false)
+    }
+
+    pub fn licenses(&self) -> Option<&LicensesDefinition> {
+        self.overrides.licenses.as_ref()
+    }
+
+    pub fn healthcheck(&self) -> Option<&HealthcheckDefinition> {
+        self.overrides.healthcheck.as_ref()
+    }
+
+    pub fn author(&self) -> Option<String> {
+        let package = self.package.as_ref()?;
+
+        if package.authors.is_empty() {
+            return None;
+        }
+
+        Some(package.authors.join(", "))
+    }
+
+    pub fn base_history(&self) -> &[LayerHistoryItem] {
+        &self.history
+    }
+
+    pub fn vcs(&self) -> Option<&VcsInfo> {
+        self.vcs.as_ref()
+    }
+
+    pub fn labels(&self) -> Option<BTreeMap<String, String>> {
+        let mut labels = BTreeMap::new();
+
+        if let Some(ref package) = self.package {
+            labels.extend(package_labels(package));
+        }
+
+        if let Some(ref vcs) = self.vcs {
+            labels.extend(vcs.labels());
+        }
+
+        if labels.is_empty() {
+            return self.overrides.labels.clone();
+        }
+
+        if let Some(ref overrides) = self.overrides.labels {
+            labels.extend(overrides.clone());
+        }
+
+        Some(labels)
+    }
 }
 
 impl BaseImageConfig for OutputConfig {
@@ -183,10 +285,80 @@ impl<'a> Into<oci::ImageConfig> for &'a OutputConfig {
                 .or_else(|| self.defaults.workdir.clone()),
 
             env: Some(self.merged_env.clone()),
-            labels: self.overrides.labels.clone(),
+            labels: self.labels(),
             volumes: self.overrides.volumes.clone(),
             exposed_ports: self.overrides.expose.clone(),
             stop_signal: self.overrides.stop_signal.or(self.defaults.stop_signal),
+            // TODO: `healthcheck` can't be propagated until `buildkit-frontend` supports `Healthcheck` field.
         }
     }
 }
+
+fn package_labels(package: &PackageDetails) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+
+    let mut add = |name: &str, value: Option<String>| {
+        if let Some(value) = value {
+            labels.insert(format!("org.opencontainers.image.{}", name), value);
+        }
+    };
+
+    add("title", Some(package.name.clone()));
+    add("version", Some(package.version.clone()));
+    add("description", package.description.clone());
+    add("licenses", package.license.clone());
+    add("url", package.homepage.clone());
+    add("source", package.repository.clone());
+    add("documentation", package.documentation.clone());
+
+    if !package.authors.is_empty() {
+        add("authors", Some(package.authors.join(", ")));
+    }
+
+    labels
+}
+
+#[test]
+fn labels_from_package() {
+    let mut config = OutputConfig::mocked_new();
+
+    config.package = Some(PackageDetails {
+        name: "my-service".into(),
+        version: "1.2.3".into(),
+        description: Some("Some service".into()),
+        authors: vec!["First <first@example.com>".into(), "Second".into()],
+        license: Some("MIT".into()),
+        homepage: None,
+        repository: Some("https://example.com/my-service".into()),
+        documentation: None,
+    });
+
+    config.overrides.labels = Some(
+        vec![(
+            String::from("org.opencontainers.image.title"),
+            String::from("overridden"),
+        )]
+        .into_iter()
+        .collect(),
+    );
+
+    assert_eq!(
+        config.author(),
+        Some("First <first@example.com>, Second".into())
+    );
+
+    assert_eq!(
+        config.labels().unwrap(),
+        vec![
+            ("authors", "First <first@example.com>, Second"),
+            ("description", "Some service"),
+            ("licenses", "MIT"),
+            ("source", "https://example.com/my-service"),
+            ("title", "overridden"),
+            ("version", "1.2.3"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (format!("org.opencontainers.image.{}", name), value.into()))
+        .collect()
+    );
+}
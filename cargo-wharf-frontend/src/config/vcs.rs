@@ -0,0 +1,246 @@
+use std::str::from_utf8;
+
+use failure::{Error, ResultExt};
+use log::*;
+use serde::Serialize;
+
+use buildkit_frontend::Bridge;
+use buildkit_llb::prelude::*;
+
+use super::{BaseImageConfig, BuilderConfig, ContextConfig};
+use crate::shared::CONTEXT_PATH;
+
+const GIT_DIR: &str = ".git";
+const OUTPUT_LAYER_PATH: &str = "/output";
+const STATUS_NAME: &str = "git-status";
+const COMMIT_TIME_NAME: &str = "git-commit-time";
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct VcsInfo {
+    pub revision: String,
+    pub dirty: Option<bool>,
+    pub commit_time: Option<i64>,
+}
+
+impl VcsInfo {
+    pub async fn analyse(
+        bridge: &mut Bridge,
+        builder: &BuilderConfig,
+        context: &ContextConfig,
+        vcs_ref: Option<&str>,
+    ) -> Result<Option<Self>, Error> {
+        if let Some(revision) = vcs_ref {
+            return Ok(Some(Self {
+                revision: revision.into(),
+                dirty: None,
+                commit_time: None,
+            }));
+        }
+
+        let source = {
+            context
+                .apply(Source::local("context").custom_name("Reading VCS revision"))
+                .add_include_pattern(format!("{}/HEAD", GIT_DIR))
+                .add_include_pattern(format!("{}/packed-refs", GIT_DIR))
+                .add_include_pattern(format!("{}/refs/**", GIT_DIR))
+        };
+
+        let layer = {
+            bridge
+                .solve(Terminal::with(source.output()))
+                .await
+                .context("Unable to read VCS revision")?
+        };
+
+        let head = match bridge
+            .read_file(&layer, format!("{}/HEAD", GIT_DIR), None)
+            .await
+        {
+            Ok(head) => from_utf8(&head)?.trim().to_owned(),
+            Err(_) => {
+                debug!("no VCS metadata was found in the build context");
+                return Ok(None);
+            }
+        };
+
+        let revision = match head_ref(&head) {
+            Some(name) => {
+                let path = format!("{}/{}", GIT_DIR, name);
+
+                match bridge.read_file(&layer, &path, None).await {
+                    Ok(contents) => Some(from_utf8(&contents)?.trim().to_owned()),
+                    Err(_) => bridge
+                        .read_file(&layer, format!("{}/packed-refs", GIT_DIR), None)
+                        .await
+                        .ok()
+                        .and_then(|contents| find_packed_ref(from_utf8(&contents).ok()?, name)),
+                }
+            }
+
+            None => Some(head.clone()),
+        };
+
+        let revision = match revision {
+            Some(revision) => revision,
+            None => {
+                warn!("unable to resolve VCS revision of '{}'", head);
+                return Ok(None);
+            }
+        };
+
+        let (dirty, commit_time) = Self::inspect_working_tree(bridge, builder, context).await;
+
+        Ok(Some(Self {
+            revision,
+            dirty,
+            commit_time,
+        }))
+    }
+
+    async fn inspect_working_tree(
+        bridge: &mut Bridge,
+        builder: &BuilderConfig,
+        context: &ContextConfig,
+    ) -> (Option<bool>, Option<i64>) {
+        let script = format!(
+            "if command -v git > /dev/null; then \
+             git -c safe.directory='*' -C {context} status --porcelain -- {pathspecs} > {output}/{status} \
+             && git -c safe.directory='*' -C {context} log -1 --format=%ct > {output}/{time}; \
+             fi",
+            context = CONTEXT_PATH,
+            pathspecs = status_pathspecs(context.exclude()).join(" "),
+            output = OUTPUT_LAYER_PATH,
+            status = STATUS_NAME,
+            time = COMMIT_TIME_NAME,
+        );
+
+        let command = {
+            builder
+                .populate_env(Command::run("/bin/sh"))
+                .args(vec![String::from("-c"), script])
+                .mount(Mount::ReadOnlyLayer(builder.source().output(), "/"))
+                .mount(Mount::ReadOnlyLayer(
+                    context.source().output(),
+                    CONTEXT_PATH,
+                ))
+                .mount(Mount::Scratch(OutputIdx(0), OUTPUT_LAYER_PATH))
+                .custom_name("Checking VCS status")
+        };
+
+        let status_layer = match bridge.solve(Terminal::with(command.output(0))).await {
+            Ok(layer) => layer,
+            Err(error) => {
+                warn!("unable to check VCS status: {}", error);
+                return (None, None);
+            }
+        };
+
+        let dirty = match bridge.read_file(&status_layer, STATUS_NAME, None).await {
+            Ok(status) => !status.is_empty(),
+            Err(_) => {
+                warn!("unable to check VCS status: git is not available in the builder image");
+                return (None, None);
+            }
+        };
+
+        let commit_time = {
+            bridge
+                .read_file(&status_layer, COMMIT_TIME_NAME, None)
+                .await
+                .ok()
+                .and_then(|contents| from_utf8(&contents).ok()?.trim().parse().ok())
+        };
+
+        (Some(dirty), commit_time)
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = (String, String)> {
+        let revision = (
+            "org.opencontainers.image.revision".into(),
+            self.revision.clone(),
+        );
+
+        let dirty = {
+            self.dirty
+                .map(|dirty| ("cargo-wharf.vcs-dirty".into(), dirty.to_string()))
+        };
+
+        Some(revision).into_iter().chain(dirty)
+    }
+}
+
+/// Limits `git status` to the files present in the build context.
+///
+/// Excluded paths are missing from the mounted context and would be reported as deleted.
+/// Re-included (`!pattern`) paths can't be expressed with pathspecs and are ignored as well.
+fn status_pathspecs<'a>(exclude: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let excluded = {
+        exclude
+            .filter(|pattern| !pattern.starts_with('!'))
+            .map(|pattern| pattern.trim_end_matches('/'))
+            .filter(|pattern| !pattern.is_empty())
+            .flat_map(|pattern| vec![pattern.to_owned(), format!("{}/**", pattern)])
+            .map(|pattern| format!(":(exclude,glob){}", pattern))
+    };
+
+    Some(String::from("."))
+        .into_iter()
+        .chain(excluded)
+        .map(|pathspec| format!("'{}'", pathspec.replace('\'', "'\\''")))
+        .collect()
+}
+
+fn head_ref(head: &str) -> Option<&str> {
+    head.strip_prefix("ref:").map(str::trim)
+}
+
+fn find_packed_ref(packed_refs: &str, name: &str) -> Option<String> {
+    packed_refs
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?, parts.next()?))
+        })
+        .find(|(_, reference)| *reference == name)
+        .map(|(revision, _)| revision.into())
+}
+
+#[test]
+fn revision_lookup() {
+    assert_eq!(
+        head_ref("ref: refs/heads/master"),
+        Some("refs/heads/master")
+    );
+    assert_eq!(head_ref("8f2a91e0c7d0b4f6f7a2e0c1d6b5e9a3c4f1d2e7"), None);
+
+    let packed_refs = "# pack-refs with: peeled fully-peeled sorted\n\
+                       0d1c6c0fd26d1bb6b3d0b1f1e52a6d5be2c1a7a1 refs/heads/master\n\
+                       6b2f1d7e7f0c4d3e1a8b9c0d1e2f3a4b5c6d7e8f refs/tags/v0.1.0\n\
+                       ^0d1c6c0fd26d1bb6b3d0b1f1e52a6d5be2c1a7a1\n";
+
+    assert_eq!(
+        find_packed_ref(packed_refs, "refs/heads/master"),
+        Some("0d1c6c0fd26d1bb6b3d0b1f1e52a6d5be2c1a7a1".into())
+    );
+
+    assert_eq!(find_packed_ref(packed_refs, "refs/heads/develop"), None);
+}
+
+#[test]
+fn status_pathspecs_of_excluded_paths() {
+    let exclude = vec!["**/target", "docs/", "!docs/README.md", "it's"];
+
+    assert_eq!(
+        status_pathspecs(exclude.into_iter()),
+        vec![
+            "'.'",
+            "':(exclude,glob)**/target'",
+            "':(exclude,glob)**/target/**'",
+            "':(exclude,glob)docs'",
+            "':(exclude,glob)docs/**'",
+            "':(exclude,glob)it'\\''s'",
+            "':(exclude,glob)it'\\''s/**'",
+        ]
+    );
+}
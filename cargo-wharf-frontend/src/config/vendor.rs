@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::from_utf8;
+
+use failure::{Error, ResultExt};
+use serde::{Deserialize, Serialize};
+
+use buildkit_frontend::Bridge;
+use buildkit_llb::prelude::*;
+
+use super::ContextConfig;
+
+const CARGO_CONFIG_FILES: &[&str] = &[".cargo/config.toml", ".cargo/config"];
+
+#[derive(Debug, Default, Serialize)]
+pub struct VendorConfig {
+    directories: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawCargoConfig {
+    #[serde(default)]
+    source: BTreeMap<String, RawSource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawSource {
+    directory: Option<PathBuf>,
+}
+
+impl VendorConfig {
+    /// Detects directory source replacements (e.g. made by `cargo vendor`) in the build context Cargo config.
+    pub async fn analyse(bridge: &mut Bridge, context: &ContextConfig) -> Result<Self, Error> {
+        let source = {
+            CARGO_CONFIG_FILES.iter().fold(
+                context.apply(Source::local("context").custom_name("Reading Cargo config")),
+                |source, name| source.add_include_pattern(*name),
+            )
+        };
+
+        let layer = {
+            bridge
+                .solve(Terminal::with(source.output()))
+                .await
+                .context("Unable to read Cargo config")?
+        };
+
+        for name in CARGO_CONFIG_FILES {
+            if let Ok(contents) = bridge.read_file(&layer, name, None).await {
+                let contents = from_utf8(&contents).context("Invalid Cargo config")?;
+
+                return Ok(Self {
+                    directories: parse_cargo_config(contents)?,
+                });
+            }
+        }
+
+        Ok(Self::default())
+    }
+
+    pub fn is_vendored(&self) -> bool {
+        !self.directories.is_empty()
+    }
+}
+
+fn parse_cargo_config(contents: &str) -> Result<Vec<PathBuf>, Error> {
+    let config: RawCargoConfig =
+        toml::from_str(contents).context("Unable to parse Cargo config")?;
+
+    Ok(config
+        .source
+        .into_values()
+        .filter_map(|source| source.directory)
+        .collect())
+}
+
+#[test]
+fn cargo_config_parsing() {
+    let contents = r#"
+        [source.crates-io]
+        replace-with = "vendored-sources"
+
+        [source.vendored-sources]
+        directory = "vendor"
+
+        [build]
+        jobs = 2
+    "#;
+
+    assert_eq!(
+        parse_cargo_config(contents).unwrap(),
+        vec![PathBuf::from("vendor")]
+    );
+
+    assert!(parse_cargo_config("[build]\njobs = 2").unwrap().is_empty());
+}
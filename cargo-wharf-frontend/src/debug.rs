@@ -22,6 +22,7 @@ pub enum DebugKind {
     Config,
     BuildPlan,
     BuildGraph,
+    Sbom,
 
     #[serde(rename = "llb")]
     LLB,
@@ -97,6 +98,15 @@ impl<'a> DebugOutput for &'a crate::graph::BuildGraph {
     }
 }
 
+impl DebugOutput for crate::query::Sbom {
+    const KEY: DebugKind = DebugKind::Sbom;
+    const PATH: &'static str = "sbom.cdx.json";
+
+    fn as_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+}
+
 impl DebugOutput for pb::Definition {
     const KEY: DebugKind = DebugKind::LLB;
     const PATH: &'static str = "llb.pb";
@@ -28,6 +28,30 @@ pub struct Options {
     pub no_default_features: bool,
     pub profile: Profile,
 
+    /// Produce an image with split debug info files instead of the binaries.
+    pub export_debuginfo: bool,
+
+    /// VCS revision to use when `.git` is not a part of the build context.
+    pub vcs_ref: Option<String>,
+
+    /// Produce reproducible binaries and images.
+    pub reproducible: bool,
+
+    /// Timestamp to use for the image and files in reproducible mode.
+    pub source_date_epoch: Option<i64>,
+
+    /// Take registry crates from `registry` named local input instead of downloading them.
+    pub local_registry: bool,
+
+    /// Evaluate the build plan and compile without accessing the network.
+    pub offline: bool,
+
+    /// Fail when `Cargo.lock` is missing or needs to be updated (enabled by default).
+    pub locked: Option<bool>,
+
+    /// Same as `locked`, while also evaluating the build plan without accessing the network.
+    pub frozen: Option<bool>,
+
     /// Debugging features of the frontend.
     pub debug: Vec<DebugKind>,
 
@@ -64,6 +88,7 @@ impl Frontend<Options> for CargoFrontend {
         let storage = WharfStorage::new(&graph, &config);
 
         debug.maybe(&options, || &graph);
+        debug.maybe(&options, || storage.bill_of_materials());
         debug.maybe(&options, || storage.definition().unwrap());
 
         if !options.debug.is_empty() {
@@ -75,8 +100,16 @@ impl Frontend<Options> for CargoFrontend {
             ));
         }
 
+        storage
+            .audit(&mut bridge)
+            .await
+            .context("Unable to audit the crates")?;
+
         Ok(FrontendOutput::with_spec_and_ref(
-            storage.image_spec().context("Unable to build image spec")?,
+            storage
+                .image_spec()
+                .context("Unable to build image spec")?
+                .into_upstream(),
             storage
                 .solve(&mut bridge, &options)
                 .await
@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::mem::replace;
 use std::path::{Path, PathBuf};
 
@@ -8,6 +9,8 @@ use serde::{Deserialize, Serialize};
 use crate::plan::{RawInvocation, RawTargetKind};
 use crate::shared::tools::{BUILDSCRIPT_APPLY, BUILDSCRIPT_CAPTURE};
 
+const EMIT_ARG_PREFIX: &str = "--emit=";
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct Node {
     package_name: String,
@@ -94,6 +97,69 @@ impl Node {
         &self.package_name
     }
 
+    pub fn package_version(&self) -> &Version {
+        &self.package_version
+    }
+
+    pub fn manifest_dir(&self) -> &Path {
+        self.command_details()
+            .env
+            .get("CARGO_MANIFEST_DIR")
+            .map(Path::new)
+            .unwrap_or_else(|| self.command_cwd())
+    }
+
+    pub fn command_cwd(&self) -> &Path {
+        &self.command_details().cwd
+    }
+
+    /// Whether the output gets linked into dependents (unlike build scripts and proc macros).
+    pub fn is_linkable(&self) -> bool {
+        match self.kind {
+            NodeKind::Primitive(PrimitiveNodeKind::Other) => {}
+            NodeKind::BuildScriptOutputConsumer(PrimitiveNodeKind::Other, _) => {}
+
+            _ => return false,
+        };
+
+        !self
+            .command_details()
+            .args
+            .windows(2)
+            .any(|pair| pair[0] == "--crate-type" && pair[1] == "proc-macro")
+    }
+
+    /// The `.rmeta` output of a library, that is enough to compile dependents which don't link it.
+    pub fn metadata_output(&self) -> Option<&Path> {
+        if !self.is_linkable() || !self.is_rlib() {
+            return None;
+        }
+
+        let emit = self.emit_arg()?;
+
+        if !emit.contains("metadata") || !emit.contains("link") {
+            return None;
+        }
+
+        self.outputs_iter()
+            .find(|path| path.extension() == Some(OsStr::new("rmeta")))
+    }
+
+    /// Command that only emits the library metadata, when the library can be pipelined.
+    pub fn metadata_command(&self) -> Option<NodeCommandDetails> {
+        self.metadata_output()?;
+
+        let mut details = self.command_details().clone();
+
+        for arg in &mut details.args {
+            if arg.starts_with(EMIT_ARG_PREFIX) {
+                *arg = format!("{}dep-info,metadata", EMIT_ARG_PREFIX);
+            }
+        }
+
+        Some(details)
+    }
+
     pub fn binary_name(&self) -> Option<&str> {
         match self.kind {
             NodeKind::Primitive(PrimitiveNodeKind::Binary) => {}
@@ -126,6 +192,28 @@ impl Node {
         &self.command
     }
 
+    fn emit_arg(&self) -> Option<&str> {
+        self.command_details()
+            .args
+            .iter()
+            .find_map(|arg| arg.strip_prefix(EMIT_ARG_PREFIX))
+    }
+
+    fn is_rlib(&self) -> bool {
+        self.command_details()
+            .args
+            .windows(2)
+            .filter(|pair| pair[0] == "--crate-type")
+            .all(|pair| pair[1] == "lib" || pair[1] == "rlib")
+    }
+
+    fn command_details(&self) -> &NodeCommandDetails {
+        match self.command {
+            NodeCommand::Simple(ref details) => details,
+            NodeCommand::WithBuildscript { ref compile, .. } => compile,
+        }
+    }
+
     pub fn into_command_details(self) -> NodeCommandDetails {
         match self.command {
             NodeCommand::Simple(details) => details,
@@ -293,3 +381,62 @@ impl From<&RawInvocation> for NodeCommandDetails {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{from_value, json};
+
+    use super::*;
+
+    #[test]
+    fn library_pipelining() {
+        let invocation = |crate_type: &str| {
+            from_value::<RawInvocation>(json!({
+                "package_name": "libc",
+                "package_version": "0.2.62",
+                "target_kind": ["lib"],
+                "deps": [],
+                "outputs": [
+                    "/target/debug/deps/liblibc-c5d8077f198ec4fc.rlib",
+                    "/target/debug/deps/liblibc-c5d8077f198ec4fc.rmeta",
+                ],
+                "links": {},
+                "program": "rustc",
+                "args": [
+                    "--crate-name", "libc", "src/lib.rs",
+                    "--crate-type", crate_type,
+                    "--emit=dep-info,metadata,link",
+                ],
+                "env": {},
+                "cwd": "/context",
+            }))
+            .unwrap()
+        };
+
+        let library = Node::from(&invocation("lib"));
+
+        assert_eq!(
+            library.metadata_output(),
+            Some(Path::new(
+                "/target/debug/deps/liblibc-c5d8077f198ec4fc.rmeta"
+            ))
+        );
+
+        assert_eq!(
+            library.metadata_command().unwrap().args,
+            vec![
+                "--crate-name",
+                "libc",
+                "src/lib.rs",
+                "--crate-type",
+                "lib",
+                "--emit=dep-info,metadata",
+            ]
+        );
+
+        let proc_macro = Node::from(&invocation("proc-macro"));
+
+        assert_eq!(proc_macro.metadata_output(), None);
+        assert_eq!(proc_macro.metadata_command(), None);
+    }
+}
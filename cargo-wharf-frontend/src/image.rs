@@ -0,0 +1,178 @@
+use log::*;
+use serde::Serialize;
+
+use buildkit_frontend::oci::{ImageConfig, ImageSpecification};
+
+use crate::config::{CustomCommandKind, HealthcheckDefinition, HealthcheckDuration};
+
+/// Image specification extended with the config fields unknown to `buildkit_frontend::oci`.
+#[derive(Debug, Serialize)]
+pub struct ImageSpecificationWrapper {
+    /// Specification without the `config`: the field is serialized from the wrapper.
+    #[serde(flatten)]
+    pub spec: ImageSpecification,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<ImageConfigWrapper>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageConfigWrapper {
+    #[serde(flatten)]
+    pub config: ImageConfig,
+
+    #[serde(rename = "Healthcheck", skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<ImageHealthcheck>,
+}
+
+/// Docker's `HEALTHCHECK` in the image config, with durations in nanoseconds.
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageHealthcheck {
+    pub test: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_period: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+}
+
+impl ImageSpecificationWrapper {
+    pub fn new(mut spec: ImageSpecification, healthcheck: Option<ImageHealthcheck>) -> Self {
+        let config = spec.config.take().map(|config| ImageConfigWrapper {
+            config,
+            healthcheck,
+        });
+
+        Self { spec, config }
+    }
+
+    /// Specification accepted by `buildkit_frontend::FrontendOutput`.
+    ///
+    /// TODO: `buildkit-frontend` 0.3 returns only the upstream specification to BuildKit,
+    /// so the `Healthcheck` is dropped until the bridge can serialize the wrapper.
+    pub fn into_upstream(self) -> ImageSpecification {
+        let Self { mut spec, config } = self;
+
+        if let Some(config) = config {
+            if config.healthcheck.is_some() {
+                warn!("The healthcheck can't be written into the image config yet, ignoring it");
+            }
+
+            spec.config = Some(config.config);
+        }
+
+        spec
+    }
+}
+
+impl<'a> From<&'a HealthcheckDefinition> for ImageHealthcheck {
+    fn from(healthcheck: &'a HealthcheckDefinition) -> Self {
+        let test = match healthcheck.test {
+            CustomCommandKind::Command(ref name_and_args) => {
+                let mut test = vec![String::from("CMD")];
+
+                test.extend(name_and_args.iter().cloned());
+                test
+            }
+
+            CustomCommandKind::Shell(ref shell) => vec![String::from("CMD-SHELL"), shell.clone()],
+        };
+
+        let nanos = |duration: Option<HealthcheckDuration>| {
+            duration.map(|duration| duration.0.as_nanos() as u64)
+        };
+
+        Self {
+            test,
+            interval: nanos(healthcheck.interval),
+            timeout: nanos(healthcheck.timeout),
+            start_period: nanos(healthcheck.start_period),
+            retries: healthcheck.retries,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use buildkit_frontend::oci::{Architecture, OperatingSystem};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn healthcheck_serialization() {
+        let definition = HealthcheckDefinition {
+            test: CustomCommandKind::Command(vec!["/usr/bin/probe".into(), "tcp".into()]),
+            interval: Some(HealthcheckDuration(Duration::from_secs(30))),
+            timeout: None,
+            start_period: Some(HealthcheckDuration(Duration::from_millis(500))),
+            retries: Some(3),
+        };
+
+        let spec = ImageSpecification {
+            created: None,
+            author: None,
+            architecture: Architecture::Amd64,
+            os: OperatingSystem::Linux,
+            config: Some(ImageConfig {
+                user: Some("root".into()),
+                exposed_ports: None,
+                env: None,
+                entrypoint: None,
+                cmd: None,
+                volumes: None,
+                working_dir: None,
+                labels: None,
+                stop_signal: None,
+            }),
+            rootfs: None,
+            history: None,
+        };
+
+        let wrapper = ImageSpecificationWrapper::new(spec, Some((&definition).into()));
+
+        assert_eq!(
+            serde_json::to_value(&wrapper).unwrap(),
+            json!({
+                "architecture": "amd64",
+                "os": "linux",
+                "config": {
+                    "User": "root",
+                    "Healthcheck": {
+                        "Test": ["CMD", "/usr/bin/probe", "tcp"],
+                        "Interval": 30_000_000_000u64,
+                        "StartPeriod": 500_000_000u64,
+                        "Retries": 3,
+                    },
+                },
+            })
+        );
+
+        let upstream = wrapper.into_upstream();
+
+        assert_eq!(upstream.config.unwrap().user, Some("root".into()));
+
+        let shell = HealthcheckDefinition {
+            test: CustomCommandKind::Shell("curl -f http://localhost".into()),
+            interval: None,
+            timeout: None,
+            start_period: None,
+            retries: None,
+        };
+
+        assert_eq!(
+            ImageHealthcheck::from(&shell).test,
+            vec!["CMD-SHELL", "curl -f http://localhost"]
+        );
+    }
+}
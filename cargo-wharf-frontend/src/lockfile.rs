@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use failure::{Error, ResultExt};
+use log::*;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use buildkit_frontend::Bridge;
+use buildkit_llb::prelude::*;
+
+use crate::config::ContextConfig;
+
+const LOCKFILE_NAME: &str = "Cargo.lock";
+const CRATES_IO_SOURCES: &[&str] = &[
+    "registry+https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io/",
+];
+
+/// Git schemes BuildKit can fetch without credentials.
+const FETCHABLE_GIT_SCHEMES: &[&str] = &["https://", "http://", "git://"];
+
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct Lockfile {
+    packages: Vec<LockedPackage>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: Version,
+    pub source: Option<String>,
+    pub checksum: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawLockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+
+    /// Checksums of the old lockfile format.
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    pub async fn analyse(
+        bridge: &mut Bridge,
+        context: &ContextConfig,
+        manifest_path: &Path,
+    ) -> Result<Option<Self>, Error> {
+        let candidates: Vec<PathBuf> = {
+            manifest_path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .ancestors()
+                .map(|dir| dir.join(LOCKFILE_NAME))
+                .collect()
+        };
+
+        let source = {
+            candidates.iter().fold(
+                context.apply(Source::local("context").custom_name("Reading Cargo.lock")),
+                |source, path| source.add_include_pattern(path.to_string_lossy()),
+            )
+        };
+
+        let layer = {
+            bridge
+                .solve(Terminal::with(source.output()))
+                .await
+                .context("Unable to read Cargo.lock")?
+        };
+
+        for path in candidates {
+            if let Ok(contents) = bridge.read_file(&layer, &path, None).await {
+                let contents = String::from_utf8(contents).context("Invalid Cargo.lock")?;
+
+                return Ok(Some(Self::parse(&contents)?));
+            }
+        }
+
+        warn!("no Cargo.lock was found in the build context");
+        Ok(None)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let raw: RawLockfile = toml::from_str(contents).context("Unable to parse Cargo.lock")?;
+        let mut packages = raw.package;
+
+        for package in &mut packages {
+            if package.checksum.is_some() {
+                continue;
+            }
+
+            let key = match package.source {
+                Some(ref source) => {
+                    format!("checksum {} {} ({})", package.name, package.version, source)
+                }
+
+                None => continue,
+            };
+
+            package.checksum = {
+                raw.metadata
+                    .get(&key)
+                    .filter(|checksum| *checksum != "<none>")
+                    .cloned()
+            };
+        }
+
+        Ok(Self { packages })
+    }
+
+    pub fn find(&self, name: &str, version: &Version) -> Option<&LockedPackage> {
+        self.packages
+            .iter()
+            .find(|package| package.name == name && package.version == *version)
+    }
+
+    /// Git dependencies that can't be fetched as git sources, e.g. from SSH remotes.
+    pub fn unfetchable_git_packages(&self) -> impl Iterator<Item = &LockedPackage> {
+        self.packages.iter().filter(|package| {
+            package.git_revision().is_some() && !package.has_fetchable_git_remote()
+        })
+    }
+}
+
+impl LockedPackage {
+    pub fn is_crates_io(&self) -> bool {
+        match self.source {
+            Some(ref source) => CRATES_IO_SOURCES.contains(&source.as_str()),
+            None => false,
+        }
+    }
+
+    /// Repository URL and the locked commit of a git dependency.
+    pub fn git_revision(&self) -> Option<(&str, &str)> {
+        let source = self.source.as_deref()?.strip_prefix("git+")?;
+        let index = source.find('#')?;
+        let url = source[..index].split('?').next().unwrap_or_default();
+
+        Some((url, &source[index + 1..]))
+    }
+
+    /// Whether BuildKit can fetch the git dependency without credentials.
+    pub fn has_fetchable_git_remote(&self) -> bool {
+        match self.git_revision() {
+            Some((url, _)) => FETCHABLE_GIT_SCHEMES
+                .iter()
+                .any(|scheme| url.starts_with(scheme)),
+
+            None => false,
+        }
+    }
+
+    /// Package URL of the crate, as used by SBOM formats.
+    pub fn purl(&self) -> String {
+        let purl = format!("pkg:cargo/{}@{}", self.name, self.version);
+
+        match self.source {
+            Some(_) if self.is_crates_io() => purl,
+
+            Some(ref source) if source.starts_with("git+") => {
+                let (url, revision) = match source.find('#') {
+                    Some(index) => (&source[..index], Some(&source[index + 1..])),
+                    None => (source.as_str(), None),
+                };
+
+                let url = url.split('?').next().unwrap_or_default();
+
+                match revision {
+                    Some(revision) => format!("{}?vcs_url={}@{}", purl, url, revision),
+                    None => format!("{}?vcs_url={}", purl, url),
+                }
+            }
+
+            Some(ref source) => {
+                let url = match source.find('+') {
+                    Some(index) => &source[index + 1..],
+                    None => source.as_str(),
+                };
+
+                format!("{}?repository_url={}", purl, url)
+            }
+
+            None => purl,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lockfile_parsing() {
+        let lockfile = Lockfile::parse(
+            r#"
+[[package]]
+name = "libc"
+version = "0.2.62"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "34fcd2c08d2f832f376f4173a231990fa5aef4e99fb569867318a227ef4c06ba"
+
+[[package]]
+name = "multi-bin"
+version = "0.1.0"
+dependencies = [
+ "libc 0.2.62 (registry+https://github.com/rust-lang/crates.io-index)",
+ "openssl-sys 0.9.50 (git+https://github.com/sfackler/rust-openssl?branch=master#c6b6fd3a)",
+]
+
+[[package]]
+name = "openssl-sys"
+version = "0.9.50"
+source = "git+https://github.com/sfackler/rust-openssl?branch=master#c6b6fd3a"
+"#,
+        )
+        .unwrap();
+
+        let libc = lockfile.find("libc", &Version::new(0, 2, 62)).unwrap();
+        let openssl = lockfile
+            .find("openssl-sys", &Version::new(0, 9, 50))
+            .unwrap();
+        let multi_bin = lockfile.find("multi-bin", &Version::new(0, 1, 0)).unwrap();
+
+        assert!(libc.is_crates_io());
+        assert_eq!(libc.purl(), "pkg:cargo/libc@0.2.62");
+        assert_eq!(
+            openssl.purl(),
+            "pkg:cargo/openssl-sys@0.9.50?vcs_url=git+https://github.com/sfackler/rust-openssl@c6b6fd3a"
+        );
+        assert_eq!(multi_bin.purl(), "pkg:cargo/multi-bin@0.1.0");
+        assert_eq!(multi_bin.checksum, None);
+
+        assert_eq!(
+            openssl.git_revision(),
+            Some(("https://github.com/sfackler/rust-openssl", "c6b6fd3a"))
+        );
+        assert_eq!(libc.git_revision(), None);
+    }
+
+    #[test]
+    fn git_remotes() {
+        let lockfile = Lockfile::parse(
+            r#"
+[[package]]
+name = "libc"
+version = "0.2.62"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "openssl-sys"
+version = "0.9.50"
+source = "git+https://github.com/sfackler/rust-openssl?branch=master#c6b6fd3a"
+
+[[package]]
+name = "private"
+version = "0.1.0"
+source = "git+ssh://git@example.com/team/private.git#4c2a1f0e"
+"#,
+        )
+        .unwrap();
+
+        let openssl = lockfile
+            .find("openssl-sys", &Version::new(0, 9, 50))
+            .unwrap();
+        let private = lockfile.find("private", &Version::new(0, 1, 0)).unwrap();
+
+        assert!(openssl.has_fetchable_git_remote());
+        assert!(!private.has_fetchable_git_remote());
+
+        assert_eq!(
+            lockfile.unfetchable_git_packages().collect::<Vec<_>>(),
+            vec![private]
+        );
+    }
+
+    #[test]
+    fn legacy_checksums() {
+        let lockfile = Lockfile::parse(
+            r#"
+[[package]]
+name = "libc"
+version = "0.2.62"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[metadata]
+"checksum libc 0.2.62 (registry+https://github.com/rust-lang/crates.io-index)" = "34fcd2c08d2f832f376f4173a231990fa5aef4e99fb569867318a227ef4c06ba"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            lockfile
+                .find("libc", &Version::new(0, 2, 62))
+                .and_then(|package| package.checksum.as_ref()),
+            Some(&String::from(
+                "34fcd2c08d2f832f376f4173a231990fa5aef4e99fb569867318a227ef4c06ba"
+            ))
+        );
+    }
+}
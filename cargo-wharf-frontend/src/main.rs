@@ -10,6 +10,8 @@ mod config;
 mod debug;
 mod frontend;
 mod graph;
+mod image;
+mod lockfile;
 mod plan;
 mod query;
 mod shared;
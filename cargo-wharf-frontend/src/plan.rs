@@ -1,5 +1,5 @@
 use std::collections::BTreeMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use failure::{Error, ResultExt};
 use semver::Version;
@@ -10,10 +10,13 @@ use buildkit_llb::prelude::*;
 
 use crate::config::{BaseImageConfig, Config};
 use crate::query::Profile;
-use crate::shared::{tools, CONTEXT, CONTEXT_PATH};
+use crate::shared::{tools, CONTEXT_PATH};
 
 const OUTPUT_LAYER_PATH: &str = "/output";
 const OUTPUT_NAME: &str = "build-plan.json";
+const REMAPPED_CARGO_HOME: &str = "/cargo";
+const FETCHED_CARGO_HOME_DIRS: &[&str] = &["registry", "git"];
+const MANIFEST_PATTERNS: &[&str] = &["**/Cargo.toml", "**/Cargo.lock"];
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RawInvocation {
@@ -80,6 +83,16 @@ impl RawBuildPlan {
             args.push(feature.into());
         }
 
+        if config.offline() {
+            args.push("--offline".into());
+        }
+
+        if config.frozen() {
+            args.push("--frozen".into());
+        } else if !config.locked() {
+            args.push("--unlocked".into());
+        }
+
         match config.profile() {
             Profile::DebugBinaries | Profile::DebugTests => {}
             Profile::ReleaseBinaries | Profile::ReleaseTests => {
@@ -87,24 +100,34 @@ impl RawBuildPlan {
             }
         }
 
-        let command = {
+        let mut command = {
             builder
                 .populate_env(Command::run(tools::BUILD_PLAN))
                 .args(&args)
                 .cwd(CONTEXT_PATH)
+                .env("CARGO_NET_OFFLINE", "true")
                 .mount(Mount::Layer(OutputIdx(0), builder.source().output(), "/"))
-                .mount(Mount::ReadOnlyLayer(CONTEXT.output(), CONTEXT_PATH))
+                .mount(Mount::ReadOnlyLayer(
+                    config.context().source().output(),
+                    CONTEXT_PATH,
+                ))
                 .mount(Mount::ReadOnlySelector(
                     tools::IMAGE.output(),
                     tools::BUILD_PLAN,
                     tools::BUILD_PLAN,
                 ))
                 .mount(Mount::Scratch(OutputIdx(1), OUTPUT_LAYER_PATH))
-                .mount(Mount::OptionalSshAgent("/run/cargo-wharf/ssh-agent-0"))
-                .env("SSH_AUTH_SOCK", "/run/cargo-wharf/ssh-agent-0")
                 .custom_name("Evaluating the build plan")
         };
 
+        for (index, (layer, path)) in config.fetched_dependencies().iter().enumerate() {
+            command = command.mount(Mount::Layer(
+                OutputIdx(index as u32 + 2),
+                layer.clone(),
+                path,
+            ));
+        }
+
         let build_plan_layer = {
             bridge
                 .solve(Terminal::with(command.output(1)))
@@ -119,6 +142,138 @@ impl RawBuildPlan {
                 .context("Unable to read Cargo build plan")?
         };
 
-        Ok(serde_json::from_slice(&build_plan).context("Unable to parse Cargo build plan")?)
+        let mut plan: Self =
+            { serde_json::from_slice(&build_plan).context("Unable to parse Cargo build plan")? };
+
+        if config.source_date_epoch().is_some() {
+            plan.remap_paths(&[
+                (Path::new(CONTEXT_PATH), Path::new(".")),
+                (builder.cargo_home(), Path::new(REMAPPED_CARGO_HOME)),
+            ]);
+        }
+
+        Ok(plan)
+    }
+
+    /// Prevent build environment paths from leaking into the binaries.
+    fn remap_paths(&mut self, prefixes: &[(&Path, &Path)]) {
+        let rustc_invocations = {
+            self.invocations
+                .iter_mut()
+                .filter(|invocation| invocation.program == "rustc")
+        };
+
+        for invocation in rustc_invocations {
+            for (from, to) in prefixes {
+                invocation.args.push(format!(
+                    "--remap-path-prefix={}={}",
+                    from.display(),
+                    to.display()
+                ));
+            }
+        }
+    }
+}
+
+/// Downloads the dependencies into Cargo home layers.
+/// Only manifests and lockfiles are taken from the build context, so editing sources doesn't trigger a new fetch.
+pub fn fetch_dependencies(config: &Config) -> Vec<(OperationOutput<'static>, PathBuf)> {
+    let builder = config.builder();
+
+    let manifests = {
+        MANIFEST_PATTERNS.iter().fold(
+            config
+                .context()
+                .apply(Source::local("context").custom_name("Using manifests")),
+            |source, pattern| source.add_include_pattern(*pattern),
+        )
+    };
+
+    let manifest_path = {
+        PathBuf::from(CONTEXT_PATH)
+            .join(config.manifest_path())
+            .to_string_lossy()
+            .into()
+    };
+
+    // Cargo refuses manifests without target sources, so the workspace gets empty ones before fetching.
+    let script = format!(
+        "{} --manifest-path \"$0\" && cargo fetch --manifest-path \"$0\" \"$@\"",
+        tools::STUB_TARGETS
+    );
+
+    let mut args = vec![String::from("-c"), script, manifest_path];
+
+    if config.frozen() {
+        args.push("--frozen".into());
+    } else if config.locked() {
+        args.push("--locked".into());
+    }
+
+    let mut command = {
+        builder
+            .populate_env(Command::run("/bin/sh"))
+            .args(args)
+            .cwd(CONTEXT_PATH)
+            .mount(Mount::Layer(OutputIdx(0), builder.source().output(), "/"))
+            .mount(Mount::Layer(
+                OutputIdx(1),
+                manifests.ref_counted().output(),
+                CONTEXT_PATH,
+            ))
+            .mount(Mount::ReadOnlySelector(
+                tools::IMAGE.output(),
+                tools::STUB_TARGETS,
+                tools::STUB_TARGETS,
+            ))
+            .mount(Mount::OptionalSshAgent("/run/cargo-wharf/ssh-agent-0"))
+            .env("SSH_AUTH_SOCK", "/run/cargo-wharf/ssh-agent-0")
+            .custom_name("Fetching the dependencies")
+    };
+
+    for (index, dir) in FETCHED_CARGO_HOME_DIRS.iter().enumerate() {
+        command = command.mount(Mount::Scratch(
+            OutputIdx(index as u32 + 2),
+            builder.cargo_home().join(dir),
+        ));
+    }
+
+    let command = command.ref_counted();
+
+    FETCHED_CARGO_HOME_DIRS
+        .iter()
+        .enumerate()
+        .map(|(index, dir)| {
+            (
+                command.output(index as u32 + 2),
+                builder.cargo_home().join(dir),
+            )
+        })
+        .collect()
+}
+
+/// Read-only mounts of the fetched dependencies, for the steps that have to read arbitrary dependencies.
+/// Offline builds take them from the builder image instead.
+pub fn fetched_dependencies_mounts(config: &Config) -> Vec<Mount<'_, PathBuf>> {
+    config
+        .fetched_dependencies()
+        .iter()
+        .map(|(layer, path)| Mount::ReadOnlyLayer(layer.clone(), path.clone()))
+        .collect()
+}
+
+#[test]
+fn paths_remapping() {
+    let mut plan: RawBuildPlan =
+        { serde_json::from_slice(include_bytes!("../tests/build-plan.json")).unwrap() };
+
+    plan.remap_paths(&[(Path::new("/context"), Path::new("."))]);
+
+    for invocation in plan.invocations {
+        let remapped = invocation
+            .args
+            .contains(&String::from("--remap-path-prefix=/context=."));
+
+        assert_eq!(remapped, invocation.program == "rustc");
     }
 }
@@ -0,0 +1,101 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use buildkit_llb::prelude::*;
+
+use crate::config::{AuditConfig, AuditMode};
+use crate::shared::tools;
+
+use super::sbom::SbomQuery;
+use super::terminal::TerminalQuery;
+
+const DATABASE_PATH: &str = "/advisory-db";
+const DATABASE_INPUT: &str = "advisory-db";
+const OUTPUT_LAYER_PATH: &str = "/output";
+
+pub trait AuditQuery: TerminalQuery + SbomQuery {
+    fn audit_terminal(&self) -> Option<Terminal<'_>> {
+        let audit = self.config().audit()?;
+
+        let database = match audit.database {
+            Some(ref path) => Mount::ReadOnlySelector(
+                self.config().context().source().output(),
+                PathBuf::from(DATABASE_PATH),
+                path.clone(),
+            ),
+
+            None => Mount::ReadOnlyLayer(
+                Source::local(DATABASE_INPUT)
+                    .custom_name("Using the advisory database")
+                    .ref_counted()
+                    .output(),
+                PathBuf::from(DATABASE_PATH),
+            ),
+        };
+
+        let command = {
+            Command::run(tools::AUDIT_CHECK)
+                .args(self.audit_args(audit))
+                .mount(Mount::ReadOnlyLayer(tools::IMAGE.output(), "/"))
+                .mount(database)
+                .mount(Mount::Scratch(OutputIdx(0), OUTPUT_LAYER_PATH))
+                .custom_name("Auditing the linked crates")
+                .ref_counted()
+        };
+
+        Some(Terminal::with(command.output(0)))
+    }
+
+    fn audit_args(&self, audit: &AuditConfig) -> Vec<String> {
+        let mut args = vec![String::from("--database"), String::from(DATABASE_PATH)];
+
+        if audit.mode == Some(AuditMode::Warn) {
+            args.push("--warn".into());
+        }
+
+        for id in audit.ignore.iter().flatten() {
+            args.push("--ignore".into());
+            args.push(id.clone());
+        }
+
+        let packages: BTreeSet<_> = {
+            self.outputs()
+                .flat_map(|output| self.linked_packages(output.index))
+                .map(|(name, version)| format!("{}@{}", name, version))
+                .collect()
+        };
+
+        args.extend(packages);
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::tests::MockStorage;
+    use crate::query::Profile;
+
+    #[test]
+    fn audit_arguments() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+        let audit = AuditConfig {
+            database: None,
+            mode: Some(AuditMode::Warn),
+            ignore: Some(vec!["RUSTSEC-2019-0001".into()]),
+        };
+
+        assert_eq!(
+            storage.audit_args(&audit),
+            vec![
+                "--database",
+                "/advisory-db",
+                "--warn",
+                "--ignore",
+                "RUSTSEC-2019-0001",
+                "libc@0.2.62",
+                "openssl-sys@0.9.50",
+            ]
+        );
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use buildkit_llb::prelude::*;
+
+use crate::graph::Node;
+use crate::lockfile::LockedPackage;
+use crate::plan::fetched_dependencies_mounts;
+use crate::shared::{tools, CONTEXT_PATH};
+
+use super::WharfDatabase;
+
+const CRATES_IO_DOWNLOAD_URL: &str = "https://static.crates.io/crates";
+const LOCAL_REGISTRY_INPUT: &str = "registry";
+const ARCHIVE_PATH: &str = "/crate";
+const OUTPUT_LAYER_PATH: &str = "/output";
+
+/// Sources of third-party crates, shared between the nodes of the same package or repository.
+pub type CrateSources = BTreeMap<String, OperationOutput<'static>>;
+
+pub trait CrateSourceQuery: WharfDatabase {
+    /// Locked registry package with a checksum, which sources the node compiles.
+    fn registry_package(&self, node: &Node) -> Option<&LockedPackage> {
+        if self.config().offline() && !self.config().local_registry() {
+            return None;
+        }
+
+        let registry_sources = self.config().builder().cargo_home().join("registry/src");
+
+        if !node.manifest_dir().starts_with(registry_sources) {
+            return None;
+        }
+
+        self.config()
+            .lockfile()?
+            .find(node.package_name(), node.package_version())
+            .filter(|package| package.is_crates_io() && package.checksum.is_some())
+    }
+
+    /// Locked git package and its checkout directory, when the node compiles a git dependency.
+    /// Only the remotes that can be fetched without credentials are supported.
+    fn git_checkout(&self, node: &Node) -> Option<(&LockedPackage, PathBuf)> {
+        if self.config().offline() {
+            return None;
+        }
+
+        let checkouts = self.config().builder().cargo_home().join("git/checkouts");
+        let relative = node.manifest_dir().strip_prefix(&checkouts).ok()?;
+
+        let package = {
+            self.config()
+                .lockfile()?
+                .find(node.package_name(), node.package_version())?
+        };
+
+        if !package.has_fetchable_git_remote() {
+            return None;
+        }
+
+        let checkout: PathBuf = relative.components().take(2).collect();
+
+        Some((package, checkouts.join(checkout)))
+    }
+
+    /// Mounts that are needed to access sources of a third-party crate.
+    /// Registry and git crates get their own sources, and anything else falls back to the fetched dependencies.
+    fn crate_source_mounts(
+        &self,
+        sources: &mut CrateSources,
+        node: &Node,
+    ) -> Vec<Mount<'_, PathBuf>> {
+        if node.manifest_dir().starts_with(CONTEXT_PATH) {
+            return vec![];
+        }
+
+        if let Some(package) = self.registry_package(node) {
+            let name = format!("{}-{}", package.name, package.version);
+            let source = {
+                sources
+                    .entry(name.clone())
+                    .or_insert_with(|| self.crate_source(package))
+            };
+
+            return vec![Mount::ReadOnlySelector(
+                source.clone(),
+                node.manifest_dir().into(),
+                name.into(),
+            )];
+        }
+
+        if let Some((package, checkout)) = self.git_checkout(node) {
+            let (url, revision) = package.git_revision().unwrap();
+            let source = {
+                sources
+                    .entry(format!("{}#{}", url, revision))
+                    .or_insert_with(|| git_source(url, revision))
+            };
+
+            return vec![Mount::ReadOnlyLayer(source.clone(), checkout)];
+        }
+
+        fetched_dependencies_mounts(self.config())
+    }
+
+    /// Directory where the sources of a third-party crate get mounted, if they aren't taken from the fetched dependencies.
+    fn crate_source_root(&self, node: &Node) -> Option<PathBuf> {
+        if self.registry_package(node).is_some() {
+            return Some(node.manifest_dir().into());
+        }
+
+        self.git_checkout(node).map(|(_, checkout)| checkout)
+    }
+
+    /// Mounts with sources of several third-party crates, where the fetched dependencies are mounted at most once.
+    fn crates_source_mounts<'a, I>(
+        &self,
+        sources: &mut CrateSources,
+        nodes: I,
+    ) -> Vec<Mount<'_, PathBuf>>
+    where
+        I: IntoIterator<Item = &'a Node>,
+    {
+        let mut mounts = vec![];
+        let mut roots = BTreeSet::new();
+        let mut needs_fetched = false;
+
+        for node in nodes {
+            if node.manifest_dir().starts_with(CONTEXT_PATH) {
+                continue;
+            }
+
+            match self.crate_source_root(node) {
+                Some(root) => {
+                    if roots.insert(root) {
+                        mounts.extend(self.crate_source_mounts(sources, node));
+                    }
+                }
+
+                None => needs_fetched = true,
+            }
+        }
+
+        if needs_fetched {
+            mounts.extend(fetched_dependencies_mounts(self.config()));
+        }
+
+        mounts
+    }
+
+    /// Unpacked sources of the registry crate, verified against the `Cargo.lock` checksum.
+    fn crate_source(&self, package: &LockedPackage) -> OperationOutput<'static> {
+        let file_name = archive_name(package);
+
+        let archive = if self.config().local_registry() {
+            Source::local(LOCAL_REGISTRY_INPUT)
+                .custom_name("Using local registry")
+                .add_include_pattern(&file_name)
+                .ref_counted()
+                .output()
+        } else {
+            Source::http(format!(
+                "{}/{}/{}",
+                CRATES_IO_DOWNLOAD_URL, package.name, file_name
+            ))
+            .with_file_name(&file_name)
+            .custom_name(format!("Downloading {} v{}", package.name, package.version))
+            .ref_counted()
+            .output()
+        };
+
+        Command::run("/bin/sh")
+            .args(vec![String::from("-c"), unpack_script(package)])
+            .mount(Mount::ReadOnlyLayer(tools::IMAGE.output(), "/"))
+            .mount(Mount::ReadOnlyLayer(archive, ARCHIVE_PATH))
+            .mount(Mount::Scratch(OutputIdx(0), OUTPUT_LAYER_PATH))
+            .custom_name(format!("Unpacking {} v{}", package.name, package.version))
+            .ref_counted()
+            .output(0)
+    }
+}
+
+fn git_source(url: &str, revision: &str) -> OperationOutput<'static> {
+    Source::git(url)
+        .with_reference(revision)
+        .custom_name(format!("Fetching {} at {}", url, revision))
+        .ref_counted()
+        .output()
+}
+
+fn archive_name(package: &LockedPackage) -> String {
+    format!("{}-{}.crate", package.name, package.version)
+}
+
+fn unpack_script(package: &LockedPackage) -> String {
+    format!(
+        "echo '{checksum}  {archive}' | sha256sum -c - && tar -xzf {archive} -C {output}",
+        checksum = package.checksum.as_deref().unwrap_or_default(),
+        archive = PathBuf::from(ARCHIVE_PATH)
+            .join(archive_name(package))
+            .display(),
+        output = OUTPUT_LAYER_PATH,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::prelude::*;
+    use prost::Message;
+    use semver::Version;
+
+    use buildkit_proto::pb::{self, op::Op};
+
+    use super::*;
+    use crate::lockfile::Lockfile;
+    use crate::query::tests::MockStorage;
+    use crate::query::Profile;
+
+    #[test]
+    fn crate_unpacking() {
+        let package = LockedPackage {
+            name: "libc".into(),
+            version: Version::new(0, 2, 62),
+            source: Some("registry+https://github.com/rust-lang/crates.io-index".into()),
+            checksum: Some(
+                "34fcd2c08d2f832f376f4173a231990fa5aef4e99fb569867318a227ef4c06ba".into(),
+            ),
+        };
+
+        assert_eq!(
+            unpack_script(&package),
+            "echo '34fcd2c08d2f832f376f4173a231990fa5aef4e99fb569867318a227ef4c06ba  /crate/libc-0.2.62.crate' \
+             | sha256sum -c - && tar -xzf /crate/libc-0.2.62.crate -C /output"
+        );
+    }
+
+    #[test]
+    fn unlocked_crate_sources() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+        let mut sources = CrateSources::new();
+
+        let third_party = &storage.graph()[NodeIndex::new(13)];
+        let local = &storage.graph()[NodeIndex::new(15)];
+
+        assert_eq!(storage.registry_package(third_party), None);
+        assert_eq!(storage.git_checkout(third_party), None);
+        assert_eq!(
+            storage.crate_source_mounts(&mut sources, third_party).len(),
+            2
+        );
+
+        assert!(storage.crate_source_mounts(&mut sources, local).is_empty());
+        assert!(sources.is_empty());
+    }
+
+    #[test]
+    fn local_registry_crate_sources() {
+        let lockfile = Lockfile::parse(
+            r#"
+[[package]]
+name = "libc"
+version = "0.2.62"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "34fcd2c08d2f832f376f4173a231990fa5aef4e99fb569867318a227ef4c06ba"
+"#,
+        )
+        .unwrap();
+
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries).with_local_registry(lockfile);
+        let mut sources = CrateSources::new();
+
+        let libc = &storage.graph()[NodeIndex::new(13)];
+        let openssl = &storage.graph()[NodeIndex::new(14)];
+
+        assert_eq!(
+            storage
+                .registry_package(libc)
+                .map(|package| &package.name[..]),
+            Some("libc")
+        );
+        assert_eq!(storage.crate_source_mounts(&mut sources, libc).len(), 1);
+
+        // Without a lockfile entry and without the network, there is nothing to fall back to.
+        assert!(storage
+            .crate_source_mounts(&mut sources, openssl)
+            .is_empty());
+
+        let definition = Terminal::with(sources["libc-0.2.62"].clone()).into_definition();
+        let operations: Vec<_> = {
+            definition
+                .def
+                .iter()
+                .filter_map(|bytes| pb::Op::decode(bytes.as_slice()).unwrap().op)
+                .collect()
+        };
+
+        let local_sources: Vec<_> = {
+            operations
+                .iter()
+                .filter_map(|op| match op {
+                    Op::Source(source) if source.identifier.starts_with("local://") => Some(source),
+
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(local_sources.len(), 1);
+        assert_eq!(local_sources[0].identifier, "local://registry");
+        assert!(local_sources[0]
+            .attrs
+            .values()
+            .any(|value| value.contains("libc-0.2.62.crate")));
+
+        assert!(operations.iter().any(|op| match op {
+            Op::Exec(exec) =>
+                exec.meta.as_ref().unwrap().args.join(" ")
+                    == format!(
+                        "/bin/sh -c {}",
+                        unpack_script(storage.registry_package(libc).unwrap())
+                    ),
+
+            _ => false,
+        }));
+    }
+}
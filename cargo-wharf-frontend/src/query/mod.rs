@@ -2,10 +2,9 @@ use std::iter::once;
 
 use async_trait::*;
 use chrono::prelude::*;
-use failure::Error;
+use failure::{Error, ResultExt};
 
 use petgraph::prelude::*;
-use petgraph::visit::Reversed;
 
 use buildkit_frontend::oci::*;
 use buildkit_frontend::{Bridge, OutputRef};
@@ -14,17 +13,25 @@ use buildkit_proto::pb;
 use crate::config::Config;
 use crate::frontend::Options;
 use crate::graph::{BuildGraph, Node};
+use crate::image::ImageSpecificationWrapper;
 use crate::shared::tools;
 
+mod audit;
+mod crates;
 mod print;
 mod profile;
+mod sbom;
 mod serialization;
 mod source;
 mod terminal;
 
 pub use self::profile::Profile;
+pub use self::sbom::Sbom;
 
+use self::audit::AuditQuery;
+use self::crates::CrateSourceQuery;
 use self::print::PrettyPrintQuery;
+use self::sbom::SbomQuery;
 use self::serialization::SerializationQuery;
 use self::source::SourceQuery;
 use self::terminal::{BuildOutput, TerminalQuery};
@@ -33,10 +40,6 @@ pub trait WharfDatabase {
     fn config(&self) -> &Config;
 
     fn graph(&self) -> &StableGraph<Node, ()>;
-
-    fn reversed_graph(&self) -> Reversed<&StableGraph<Node, ()>> {
-        Reversed(self.graph())
-    }
 }
 
 pub struct WharfStorage<'a> {
@@ -45,7 +48,7 @@ pub struct WharfStorage<'a> {
 }
 
 #[async_trait]
-pub trait WharfQuery: TerminalQuery {
+pub trait WharfQuery: TerminalQuery + AuditQuery {
     fn definition(&self) -> Result<pb::Definition, Error> {
         Ok(self.terminal()?.into_definition())
     }
@@ -56,8 +59,24 @@ pub trait WharfQuery: TerminalQuery {
             .await
     }
 
-    fn image_spec(&self) -> Result<ImageSpecification, Error> {
+    async fn audit(&self, bridge: &mut Bridge) -> Result<(), Error> {
+        if let Some(terminal) = self.audit_terminal() {
+            bridge
+                .solve(terminal)
+                .await
+                .context("Found unignored security advisories")?;
+        }
+
+        Ok(())
+    }
+
+    fn bill_of_materials(&self) -> Sbom {
+        self.sbom(self.outputs())
+    }
+
+    fn image_spec(&self) -> Result<ImageSpecificationWrapper, Error> {
         let output = self.config().output();
+        let created = self.config().creation_time();
 
         let config = match self.config().profile() {
             Profile::ReleaseBinaries | Profile::DebugBinaries => self.config().output().into(),
@@ -89,9 +108,14 @@ pub trait WharfQuery: TerminalQuery {
             },
         };
 
-        Ok(ImageSpecification {
-            created: Some(Utc::now()),
-            author: None,
+        let healthcheck = match self.config().profile() {
+            Profile::ReleaseBinaries | Profile::DebugBinaries => output.healthcheck(),
+            Profile::ReleaseTests | Profile::DebugTests => None,
+        };
+
+        let spec = ImageSpecification {
+            created: Some(created),
+            author: output.author(),
 
             // TODO: don't hardcode this
             architecture: Architecture::Amd64,
@@ -99,8 +123,34 @@ pub trait WharfQuery: TerminalQuery {
 
             config: Some(config),
             rootfs: None,
-            history: None,
-        })
+            history: Some(self.history(created)),
+        };
+
+        Ok(ImageSpecificationWrapper::new(
+            spec,
+            healthcheck.map(Into::into),
+        ))
+    }
+
+    fn history(&self, created: DateTime<Utc>) -> Vec<LayerHistoryItem> {
+        let output = self.config().output();
+        let author = output.author();
+
+        let mut history = if self.config().export_debuginfo() {
+            vec![]
+        } else {
+            output.base_history().to_vec()
+        };
+
+        history.extend(self.output_steps().iter().map(|step| LayerHistoryItem {
+            created: Some(created),
+            author: author.clone(),
+            created_by: Some(step.description()),
+            comment: None,
+            empty_layer: if step.is_layer() { None } else { Some(true) },
+        }));
+
+        history
     }
 }
 
@@ -119,6 +169,9 @@ impl<'a> TerminalQuery for WharfStorage<'a> {}
 impl<'a> SerializationQuery for WharfStorage<'a> {}
 impl<'a> SourceQuery for WharfStorage<'a> {}
 impl<'a> PrettyPrintQuery for WharfStorage<'a> {}
+impl<'a> SbomQuery for WharfStorage<'a> {}
+impl<'a> AuditQuery for WharfStorage<'a> {}
+impl<'a> CrateSourceQuery for WharfStorage<'a> {}
 
 impl<'a> WharfStorage<'a> {
     pub fn new(graph: &'a BuildGraph, config: &'a Config) -> Self {
@@ -137,6 +190,7 @@ mod tests {
 
     use super::*;
     use crate::config::{BinaryDefinition, BuilderConfig, OutputConfig};
+    use crate::lockfile::Lockfile;
     use crate::plan::RawBuildPlan;
 
     pub struct MockStorage {
@@ -146,21 +200,39 @@ mod tests {
 
     impl MockStorage {
         pub fn mocked(profile: Profile) -> Self {
-            let graph = BuildGraph::from(
-                from_slice::<RawBuildPlan>(include_bytes!("../../tests/build-plan.json")).unwrap(),
-            );
+            Self::with_plan(profile, include_bytes!("../../tests/build-plan.json"))
+        }
+
+        /// Same build plan, as Cargo emits it when the libraries are pipelined.
+        pub fn pipelined(profile: Profile) -> Self {
+            Self::with_plan(
+                profile,
+                include_bytes!("../../tests/build-plan-pipelined.json"),
+            )
+        }
 
-            let builder = BuilderConfig::mocked_new(Source::image("rust"), "/root/.cargo".into());
+        fn with_plan(profile: Profile, plan: &[u8]) -> Self {
+            let graph = BuildGraph::from(from_slice::<RawBuildPlan>(plan).unwrap());
+
+            let builder = BuilderConfig::mocked_new(Source::image("rust"), "/home/.cargo".into());
             let output = OutputConfig::mocked_new();
 
             let binaries = vec![
                 BinaryDefinition {
                     name: "bin-1".into(),
                     destination: "/usr/bin/mock-binary-1".into(),
+                    strip: None,
+                    split_debuginfo: None,
+                    transform: None,
+                    permissions: Default::default(),
                 },
                 BinaryDefinition {
                     name: "bin-3".into(),
                     destination: "/bin/binary-3".into(),
+                    strip: None,
+                    split_debuginfo: None,
+                    transform: None,
+                    permissions: Default::default(),
                 },
             ];
 
@@ -171,6 +243,21 @@ mod tests {
                 config,
             }
         }
+
+        pub fn reproducible(mut self, epoch: i64) -> Self {
+            self.config = self.config.with_source_date_epoch(epoch);
+            self
+        }
+
+        pub fn with_workspace_build_scripts(mut self, packages: Vec<String>) -> Self {
+            self.config = self.config.with_workspace_build_scripts(packages);
+            self
+        }
+
+        pub fn with_local_registry(mut self, lockfile: Lockfile) -> Self {
+            self.config = self.config.with_local_registry(lockfile);
+            self
+        }
     }
 
     impl WharfDatabase for MockStorage {
@@ -188,4 +275,48 @@ mod tests {
     impl SerializationQuery for MockStorage {}
     impl SourceQuery for MockStorage {}
     impl PrettyPrintQuery for MockStorage {}
+    impl SbomQuery for MockStorage {}
+    impl AuditQuery for MockStorage {}
+    impl CrateSourceQuery for MockStorage {}
+
+    #[test]
+    fn history_of_layers() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries).reproducible(1_500_000_000);
+        let history = storage.history(Utc.timestamp(1_500_000_000, 0));
+
+        assert_eq!(
+            history
+                .iter()
+                .map(|item| (item.created_by.as_deref().unwrap(), item.empty_layer))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Composing the output image", None),
+                ("Normalizing directories timestamps", None),
+                ("Setting the image config", Some(true)),
+            ]
+        );
+
+        let definition = storage.definition().unwrap();
+        let operations: Vec<_> = {
+            definition
+                .metadata
+                .values()
+                .filter_map(|metadata| metadata.description.get("llb.customname"))
+                .collect()
+        };
+
+        for item in history.iter().filter(|item| item.empty_layer.is_none()) {
+            let created_by = item.created_by.as_ref().unwrap();
+
+            assert_eq!(
+                operations
+                    .iter()
+                    .filter(|name| **name == created_by)
+                    .count(),
+                1,
+                "missing operation for '{}'",
+                created_by
+            );
+        }
+    }
 }
@@ -4,8 +4,11 @@ pub enum PrintKind<S> {
     CompileBinary(S),
     CompileTest(S),
     CompileCrate(S),
+    CompileMetadata(S),
 
     RunBuildScript(S),
+    StripBinary(S),
+    MergeDependencies(S),
 }
 
 pub trait PrettyPrintQuery {
@@ -19,6 +22,10 @@ pub trait PrettyPrintQuery {
             PrintKind::CompileTest(name) => format!("Compiling test {}", name.as_ref()),
             PrintKind::CompileCrate(name) => format!("Compiling {}", name.as_ref()),
 
+            PrintKind::CompileMetadata(name) => {
+                format!("Compiling {} [metadata]", name.as_ref())
+            }
+
             PrintKind::CompileBuildScript(name) => {
                 format!("Compiling {} [build script]", name.as_ref())
             }
@@ -26,6 +33,12 @@ pub trait PrettyPrintQuery {
             PrintKind::RunBuildScript(name) => {
                 format!("Running   {} [build script]", name.as_ref())
             }
+
+            PrintKind::StripBinary(name) => format!("Stripping binary {}", name.as_ref()),
+
+            PrintKind::MergeDependencies(name) => {
+                format!("Merging   {} [dependencies]", name.as_ref())
+            }
         }
     }
 }
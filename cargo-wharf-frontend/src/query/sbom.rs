@@ -0,0 +1,247 @@
+use std::collections::BTreeSet;
+
+use petgraph::prelude::*;
+use semver::Version;
+use serde::Serialize;
+
+use crate::lockfile::LockedPackage;
+
+use super::terminal::BuildOutput;
+use super::WharfDatabase;
+
+const SPEC_VERSION: &str = "1.4";
+
+/// Software Bill of Materials in CycloneDX JSON format.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sbom {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: SbomMetadata,
+    components: Vec<SbomComponent>,
+    dependencies: Vec<SbomDependency>,
+}
+
+#[derive(Debug, Serialize)]
+struct SbomMetadata {
+    timestamp: String,
+    tools: Vec<SbomTool>,
+}
+
+#[derive(Debug, Serialize)]
+struct SbomTool {
+    vendor: &'static str,
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct SbomComponent {
+    #[serde(rename = "type")]
+    kind: &'static str,
+
+    #[serde(rename = "bom-ref")]
+    bom_ref: String,
+
+    name: String,
+    version: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    purl: Option<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hashes: Vec<SbomHash>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+struct SbomHash {
+    alg: &'static str,
+    content: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct SbomDependency {
+    #[serde(rename = "ref")]
+    reference: String,
+    depends_on: Vec<String>,
+}
+
+pub trait SbomQuery: WharfDatabase {
+    fn sbom<'a, I>(&self, outputs: I) -> Sbom
+    where
+        I: IntoIterator<Item = BuildOutput<'a>>,
+    {
+        let mut packages = BTreeSet::new();
+        let mut binaries = vec![];
+        let mut dependencies = vec![];
+
+        for output in outputs {
+            let linked = self.linked_packages(output.index);
+            let name = {
+                output
+                    .path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            };
+
+            let binary = SbomComponent {
+                kind: "application",
+                bom_ref: format!("binary:{}", output.path.display()),
+                name,
+                version: output.node.package_version().to_string(),
+                purl: None,
+                hashes: vec![],
+            };
+
+            dependencies.push(SbomDependency {
+                reference: binary.bom_ref.clone(),
+                depends_on: {
+                    linked
+                        .iter()
+                        .map(|(name, version)| self.package_component(name, version).bom_ref)
+                        .collect()
+                },
+            });
+
+            binaries.push(binary);
+            packages.extend(linked);
+        }
+
+        let components = {
+            binaries
+                .into_iter()
+                .chain(
+                    packages
+                        .into_iter()
+                        .map(|(name, version)| self.package_component(name, version)),
+                )
+                .collect()
+        };
+
+        Sbom {
+            bom_format: "CycloneDX",
+            spec_version: SPEC_VERSION,
+            version: 1,
+
+            metadata: SbomMetadata {
+                timestamp: self.config().creation_time().to_rfc3339(),
+                tools: vec![SbomTool {
+                    vendor: "cargo-wharf",
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                }],
+            },
+
+            components,
+            dependencies,
+        }
+    }
+
+    fn linked_packages(&self, index: NodeIndex) -> BTreeSet<(&str, &Version)> {
+        self.linked_nodes(index)
+            .into_iter()
+            .map(|index| &self.graph()[index])
+            .map(|node| (node.package_name(), node.package_version()))
+            .collect()
+    }
+
+    /// Collect nodes that are linked into the output, excluding the output itself and build-only dependencies.
+    fn linked_nodes(&self, index: NodeIndex) -> BTreeSet<NodeIndex> {
+        let graph = self.graph();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![index];
+
+        while let Some(current) = pending.pop() {
+            if !visited.insert(current) {
+                continue;
+            }
+
+            pending.extend(
+                graph
+                    .neighbors_directed(current, Direction::Incoming)
+                    .filter(|dependency| graph[*dependency].is_linkable()),
+            );
+        }
+
+        visited.remove(&index);
+        visited
+    }
+
+    fn package_component(&self, name: &str, version: &Version) -> SbomComponent {
+        let locked = {
+            self.config()
+                .lockfile()
+                .and_then(|lockfile| lockfile.find(name, version))
+        };
+
+        let purl = {
+            locked
+                .map(LockedPackage::purl)
+                .unwrap_or_else(|| format!("pkg:cargo/{}@{}", name, version))
+        };
+
+        let hashes = {
+            locked
+                .and_then(|package| package.checksum.clone())
+                .map(|content| SbomHash {
+                    alg: "SHA-256",
+                    content,
+                })
+                .into_iter()
+                .collect()
+        };
+
+        SbomComponent {
+            kind: "library",
+            bom_ref: purl.clone(),
+            name: name.into(),
+            version: version.to_string(),
+            purl: Some(purl),
+            hashes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::tests::MockStorage;
+    use crate::query::{Profile, TerminalQuery};
+
+    #[test]
+    fn binary_linked_packages() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+
+        assert_eq!(
+            storage.linked_packages(NodeIndex::new(15)),
+            vec![
+                ("libc", &Version::new(0, 2, 62)),
+                ("openssl-sys", &Version::new(0, 9, 50)),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn binary_dependencies() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+        let sbom = storage.sbom(storage.outputs());
+
+        assert_eq!(
+            sbom.dependencies,
+            vec![SbomDependency {
+                reference: "binary:/usr/bin/mock-binary-1".into(),
+                depends_on: vec![
+                    "pkg:cargo/libc@0.2.62".into(),
+                    "pkg:cargo/openssl-sys@0.9.50".into(),
+                ],
+            }]
+        );
+
+        assert_eq!(sbom.components.len(), 3);
+    }
+}
@@ -1,131 +1,270 @@
+use std::collections::BTreeSet;
+use std::iter::once;
 use std::path::{Path, PathBuf};
 
 use petgraph::prelude::*;
-use petgraph::visit::{Topo, Walker};
+use petgraph::visit::Topo;
 
 use buildkit_llb::prelude::*;
 
 use crate::config::{BaseImageConfig, Config};
 use crate::graph::{Node, NodeCommand, NodeCommandDetails, NodeKind, PrimitiveNodeKind};
-use crate::shared::{tools, CONTEXT, CONTEXT_PATH, TARGET_PATH};
+use crate::shared::{tools, CONTEXT_PATH, TARGET_PATH};
 
+use super::crates::{CrateSourceQuery, CrateSources};
 use super::print::{PrettyPrintQuery, PrintKind};
 use super::{SourceQuery, WharfDatabase};
 
 type NodesCache<'a> = Vec<Option<OperationOutput<'a>>>;
 
-pub trait SerializationQuery: WharfDatabase + SourceQuery + PrettyPrintQuery {
-    fn serialize_all_nodes(&self) -> NodesCache<'_> {
-        let mut nodes = vec![None; self.graph().capacity().0];
-        let mut deps = vec![None; self.graph().capacity().0];
+pub trait SerializationQuery:
+    WharfDatabase + SourceQuery + PrettyPrintQuery + CrateSourceQuery
+{
+    /// Serialize only the nodes that are needed to build the `roots`.
+    fn serialize_nodes<I>(&self, roots: I) -> NodesCache<'_>
+    where
+        I: IntoIterator<Item = NodeIndex>,
+    {
+        let required = self.required_nodes(roots);
+        let capacity = self.graph().capacity().0;
+
+        let mut nodes = vec![None; capacity];
+        let mut metadata = vec![None; capacity];
+        let mut merged = vec![None; capacity];
+        let mut transitive = vec![BTreeSet::new(); capacity];
+        let mut crate_sources = CrateSources::new();
 
         let mut visitor = Topo::new(self.graph());
 
         while let Some(index) = visitor.next(self.graph()) {
-            self.maybe_cache_dependencies(&nodes, &mut deps, index);
+            if !required.contains(&index) {
+                continue;
+            }
+
+            transitive[index.index()] = self.transitive_dependencies(&transitive, index);
+
+            let node = self.graph().node_weight(index).unwrap();
+            let dependencies = &transitive[index.index()];
+            let context_dirs = self.context_dirs(index, dependencies);
+            let crate_mounts = self.crate_source_mounts(&mut crate_sources, node);
+
+            // Libraries don't link their dependencies, so only metadata of them is needed.
+            let pipelined = node.metadata_output().is_some();
+            let deps_layer = if dependencies.is_empty() {
+                None
+            } else {
+                Some(self.merge_dependencies(&nodes, &metadata, &transitive, &merged, index))
+            };
+
+            if let Some(details) = node.metadata_command() {
+                let (metadata_llb, output) = self.serialize_metadata(
+                    &self.config(),
+                    self.builder_source().clone().unwrap(),
+                    deps_layer.clone(),
+                    &source_mounts(&self.config(), &context_dirs, &crate_mounts),
+                    node,
+                    &details,
+                );
+
+                metadata[index.index()] = Some(metadata_llb.ref_counted().output(output.0));
+            }
 
             let (node_llb, output) = self.serialize_node(
                 &self.config(),
                 self.builder_source().clone().unwrap(),
-                deps[index.index()].as_ref().unwrap(),
-                self.graph().node_weight(index).unwrap(),
+                deps_layer.clone(),
+                &context_dirs,
+                &crate_mounts,
+                node,
             );
 
+            merged[index.index()] = deps_layer.map(|layer| (pipelined, layer));
             nodes[index.index()] = Some(node_llb.ref_counted().output(output.0));
         }
 
         nodes
     }
 
-    fn maybe_cache_dependencies<'a>(
+    /// Collect the `roots` together with all their transitive dependencies.
+    fn required_nodes<I>(&self, roots: I) -> BTreeSet<NodeIndex>
+    where
+        I: IntoIterator<Item = NodeIndex>,
+    {
+        let mut required = BTreeSet::new();
+        let mut pending: Vec<_> = roots.into_iter().collect();
+
+        while let Some(index) = pending.pop() {
+            if required.insert(index) {
+                pending.extend(self.graph().neighbors_directed(index, Direction::Incoming));
+            }
+        }
+
+        required
+    }
+
+    /// Transitive dependencies of the node, assembled from the already known sets of its direct dependencies.
+    fn transitive_dependencies(
+        &self,
+        known: &[BTreeSet<NodeIndex>],
+        index: NodeIndex,
+    ) -> BTreeSet<NodeIndex> {
+        let mut dependencies = BTreeSet::new();
+
+        for dependency in self.graph().neighbors_directed(index, Direction::Incoming) {
+            dependencies.insert(dependency);
+            dependencies.extend(&known[dependency.index()]);
+        }
+
+        dependencies
+    }
+
+    /// Copy outputs of the dependencies into a single layer, that is used as a base for the target dir.
+    /// Pipelined nodes get only `.rmeta` of the dependencies that produce it.
+    /// The biggest merged layer of the same kind among direct dependencies is reused,
+    /// so only the rest gets copied.
+    fn merge_dependencies<'a>(
         &self,
         nodes: &[Option<OperationOutput<'a>>],
-        deps: &mut Vec<Option<Vec<Mount<'a, PathBuf>>>>,
+        metadata: &[Option<OperationOutput<'a>>],
+        transitive: &[BTreeSet<NodeIndex>],
+        merged: &[Option<(bool, OperationOutput<'a>)>],
         index: NodeIndex,
-    ) {
-        if deps[index.index()].is_some() {
-            return;
+    ) -> OperationOutput<'a> {
+        let pipelined = self.graph()[index].metadata_output().is_some();
+
+        let base = {
+            self.graph()
+                .neighbors_directed(index, Direction::Incoming)
+                .filter(|dep_index| match merged[dep_index.index()] {
+                    Some((kind, _)) => kind == pipelined,
+                    None => false,
+                })
+                .max_by_key(|dep_index| transitive[dep_index.index()].len())
+        };
+
+        let (base_layer, covered) = match base {
+            Some(base) => (
+                merged[base.index()].clone().map(|(_, layer)| layer),
+                transitive[base.index()].clone(),
+            ),
+            None => (None, BTreeSet::new()),
+        };
+
+        let name = self.pretty_print(PrintKind::MergeDependencies(
+            self.graph()[index].package_name(),
+        ));
+
+        let mut operation = FileSystem::sequence().custom_name(name);
+
+        for dep_index in transitive[index.index()].difference(&covered) {
+            let dependency = &self.graph()[*dep_index];
+            let (layer, paths) = match dependency.metadata_output() {
+                Some(path) if pipelined => (&metadata[dep_index.index()], vec![path]),
+                _ => (
+                    &nodes[dep_index.index()],
+                    dependency.outputs_iter().collect(),
+                ),
+            };
+
+            for path in paths {
+                let path = path.strip_prefix(TARGET_PATH).unwrap();
+
+                let (output, destination) = match (operation.last_output_index(), &base_layer) {
+                    (Some(index), _) => (index + 1, LayerPath::Own(OwnOutputIdx(index), path)),
+                    (None, Some(base)) => (0, LayerPath::Other(base.clone(), path)),
+                    (None, None) => (0, LayerPath::Scratch(path)),
+                };
+
+                operation = operation.append(
+                    FileSystem::copy()
+                        .from(LayerPath::Other(layer.clone().unwrap(), path))
+                        .to(OutputIdx(output), destination)
+                        .create_path(true)
+                        .recursive(true),
+                );
+            }
         }
 
-        let local_deps = DfsPostOrder::new(&self.reversed_graph(), index)
-            .iter(self.reversed_graph())
-            .filter(|dep_index| dep_index.index() != index.index())
-            .flat_map(|dep_index| {
-                self.graph()
-                    .node_weight(dep_index)
-                    .unwrap()
-                    .outputs_iter()
-                    .map(move |path| {
-                        Mount::ReadOnlySelector(
-                            nodes[dep_index.index()].clone().unwrap(),
-                            path.into(),
-                            path.strip_prefix(TARGET_PATH).unwrap().into(),
-                        )
-                    })
-            });
-
-        deps[index.index()] = Some(local_deps.collect());
+        operation.ref_counted().last_output().unwrap()
+    }
+
+    /// Parts of the build context the node can access: the package itself and its path dependencies.
+    fn context_dirs(&self, index: NodeIndex, dependencies: &BTreeSet<NodeIndex>) -> Vec<PathBuf> {
+        let node = self.graph().node_weight(index).unwrap();
+
+        let dirs: BTreeSet<_> = {
+            once(&index)
+                .chain(dependencies)
+                .map(|dep_index| self.graph().node_weight(*dep_index).unwrap().manifest_dir())
+                .filter(|dir| dir.starts_with(CONTEXT_PATH))
+                .collect()
+        };
+
+        if dirs.is_empty() && node.command_cwd().starts_with(CONTEXT_PATH) {
+            return vec![CONTEXT_PATH.into()];
+        }
+
+        outermost_dirs(dirs)
+    }
+
+    /// Build scripts see the same directories as the compilation, unless the package is opted in
+    /// to read the whole build context (e.g. shared `.proto` files).
+    fn build_script_context_dirs(&self, node: &Node, context_dirs: &[PathBuf]) -> Vec<PathBuf> {
+        let context = self.config().context();
+
+        if !context_dirs.is_empty() && context.has_workspace_build_script(node.package_name()) {
+            vec![PathBuf::from(CONTEXT_PATH)]
+        } else {
+            context_dirs.to_vec()
+        }
     }
 
     fn serialize_node<'a>(
         &self,
         config: &'a Config,
         source: OperationOutput<'a>,
-        deps: &[Mount<'a, PathBuf>],
+        deps_layer: Option<OperationOutput<'a>>,
+        context_dirs: &[PathBuf],
+        crate_mounts: &[Mount<'a, PathBuf>],
         node: &'a Node,
     ) -> (Command<'a>, OutputIdx) {
+        let mounts = source_mounts(config, context_dirs, crate_mounts);
+
         let (mut command, index) = match node.command() {
             NodeCommand::Simple(ref details) => self.serialize_command(
                 config,
                 source,
-                self.create_target_dirs(node.output_dirs_iter()),
+                self.create_target_dirs(deps_layer.clone(), node.output_dirs_iter()),
                 details,
+                &mounts,
             ),
 
             NodeCommand::WithBuildscript { compile, run } => {
                 let (mut compile_command, compile_index) = self.serialize_command(
                     config,
                     source.clone(),
-                    self.create_target_dirs(node.output_dirs_iter()),
+                    self.create_target_dirs(deps_layer.clone(), node.output_dirs_iter()),
                     compile,
+                    &mounts,
                 );
 
                 compile_command = compile_command.custom_name(
                     self.pretty_print(PrintKind::CompileBuildScript(node.package_name())),
                 );
 
-                for mount in deps {
-                    compile_command = compile_command.mount(mount.clone());
-                }
+                let run_context_dirs = self.build_script_context_dirs(node, context_dirs);
 
                 self.serialize_command(
                     config,
                     source,
                     compile_command.ref_counted().output(compile_index.0),
                     run,
+                    &source_mounts(config, &run_context_dirs, crate_mounts),
                 )
             }
         };
 
-        for mount in deps {
-            command = command.mount(mount.clone());
-        }
-
-        if let NodeKind::BuildScriptOutputConsumer(_, _) = node.kind() {
-            command = command.mount(Mount::ReadOnlySelector(
-                tools::IMAGE.output(),
-                tools::BUILDSCRIPT_APPLY,
-                tools::BUILDSCRIPT_APPLY,
-            ));
-        }
-
-        if let NodeKind::MergedBuildScript(_) = node.kind() {
-            command = command.mount(Mount::ReadOnlySelector(
-                tools::IMAGE.output(),
-                tools::BUILDSCRIPT_CAPTURE,
-                tools::BUILDSCRIPT_CAPTURE,
-            ));
-        }
+        command = self.mount_wrappers(command, node);
 
         let print_kind = match node.kind() {
             NodeKind::BuildScriptOutputConsumer(PrimitiveNodeKind::Binary, _) => {
@@ -152,12 +291,56 @@ pub trait SerializationQuery: WharfDatabase + SourceQuery + PrettyPrintQuery {
         (command.custom_name(self.pretty_print(print_kind)), index)
     }
 
-    fn serialize_command<'a, 'b: 'a>(
+    /// Metadata-only compilation of a library, that lets the dependents start earlier.
+    fn serialize_metadata<'a>(
+        &self,
+        config: &'a Config,
+        source: OperationOutput<'a>,
+        deps_layer: Option<OperationOutput<'a>>,
+        mounts: &[Mount<'a, PathBuf>],
+        node: &Node,
+        details: &NodeCommandDetails,
+    ) -> (Command<'a>, OutputIdx) {
+        let (command, index) = self.serialize_command(
+            config,
+            source,
+            self.create_target_dirs(deps_layer, node.output_dirs_iter()),
+            details,
+            mounts,
+        );
+
+        let name = self.pretty_print(PrintKind::CompileMetadata(node.package_name()));
+
+        (self.mount_wrappers(command, node).custom_name(name), index)
+    }
+
+    fn mount_wrappers<'a>(&self, mut command: Command<'a>, node: &Node) -> Command<'a> {
+        if let NodeKind::BuildScriptOutputConsumer(_, _) = node.kind() {
+            command = command.mount(Mount::ReadOnlySelector(
+                tools::IMAGE.output(),
+                tools::BUILDSCRIPT_APPLY,
+                tools::BUILDSCRIPT_APPLY,
+            ));
+        }
+
+        if let NodeKind::MergedBuildScript(_) = node.kind() {
+            command = command.mount(Mount::ReadOnlySelector(
+                tools::IMAGE.output(),
+                tools::BUILDSCRIPT_CAPTURE,
+                tools::BUILDSCRIPT_CAPTURE,
+            ));
+        }
+
+        command
+    }
+
+    fn serialize_command<'a>(
         &self,
         config: &'a Config,
         source: OperationOutput<'a>,
-        target_layer: OperationOutput<'b>,
-        command: &'b NodeCommandDetails,
+        target_layer: OperationOutput<'a>,
+        command: &NodeCommandDetails,
+        mounts: &[Mount<'a, PathBuf>],
     ) -> (Command<'a>, OutputIdx) {
         let builder = config.builder();
 
@@ -172,25 +355,28 @@ pub trait SerializationQuery: WharfDatabase + SourceQuery + PrettyPrintQuery {
                 .mount(Mount::Scratch(OutputIdx(1), "/tmp"))
         };
 
-        if command.cwd.starts_with(CONTEXT_PATH) {
-            command_llb = command_llb.mount(Mount::ReadOnlyLayer(CONTEXT.output(), CONTEXT_PATH));
+        for mount in mounts {
+            command_llb = command_llb.mount(mount.clone());
         }
 
         (command_llb, OutputIdx(0))
     }
 
-    fn create_target_dirs<'a>(
+    /// Prepare the target layer, optionally on top of the merged dependencies layer.
+    fn create_target_dirs<'a, 'b>(
         &self,
-        outputs: impl Iterator<Item = &'a Path>,
-    ) -> OperationOutput<'static> {
+        base: Option<OperationOutput<'a>>,
+        outputs: impl Iterator<Item = &'b Path>,
+    ) -> OperationOutput<'a> {
         let mut operation = FileSystem::sequence();
 
         for output in outputs {
             let path = output.strip_prefix(TARGET_PATH).unwrap();
 
-            let (index, layer_path) = match operation.last_output_index() {
-                Some(index) => (index + 1, LayerPath::Own(OwnOutputIdx(index), path)),
-                None => (0, LayerPath::Scratch(path)),
+            let (index, layer_path) = match (operation.last_output_index(), &base) {
+                (Some(index), _) => (index + 1, LayerPath::Own(OwnOutputIdx(index), path)),
+                (None, Some(base)) => (0, LayerPath::Other(base.clone(), path)),
+                (None, None) => (0, LayerPath::Scratch(path)),
             };
 
             let inner = FileSystem::mkdir(OutputIdx(index), layer_path).make_parents(true);
@@ -201,3 +387,223 @@ pub trait SerializationQuery: WharfDatabase + SourceQuery + PrettyPrintQuery {
         operation.ref_counted().last_output().unwrap()
     }
 }
+
+/// Read-only sources of a command: parts of the build context and third-party crates.
+fn source_mounts<'a>(
+    config: &'a Config,
+    context_dirs: &[PathBuf],
+    crate_mounts: &[Mount<'a, PathBuf>],
+) -> Vec<Mount<'a, PathBuf>> {
+    let context = config.context().source();
+
+    context_dirs
+        .iter()
+        .map(|dir| match dir.strip_prefix(CONTEXT_PATH) {
+            Ok(selector) if selector != Path::new("") => {
+                Mount::ReadOnlySelector(context.output(), dir.clone(), selector.into())
+            }
+
+            _ => Mount::ReadOnlyLayer(context.output(), dir.clone()),
+        })
+        .chain(crate_mounts.iter().cloned())
+        .collect()
+}
+
+/// Drop directories that are already covered by their parents.
+fn outermost_dirs(dirs: BTreeSet<&Path>) -> Vec<PathBuf> {
+    let mut outermost: Vec<PathBuf> = vec![];
+
+    for dir in dirs {
+        if !outermost.iter().any(|parent| dir.starts_with(parent)) {
+            outermost.push(dir.into());
+        }
+    }
+
+    outermost
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use buildkit_proto::pb::{self, file_action::Action, op::Op};
+
+    use super::*;
+    use crate::query::tests::MockStorage;
+    use crate::query::{Profile, TerminalQuery};
+
+    #[test]
+    fn binaries_required_nodes() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+        let required = storage.required_nodes(storage.outputs().map(|output| output.index));
+
+        assert!(required.contains(&NodeIndex::new(15)));
+        assert!(required.contains(&NodeIndex::new(14)));
+        assert!(!required.contains(&NodeIndex::new(16)));
+        assert!(!required.contains(&NodeIndex::new(17)));
+        assert!(!required.contains(&NodeIndex::new(18)));
+        assert_eq!(required.len(), 13);
+    }
+
+    #[test]
+    fn tests_required_nodes() {
+        let storage = MockStorage::mocked(Profile::ReleaseTests);
+        let required = storage.required_nodes(storage.outputs().map(|output| output.index));
+
+        assert!(required.contains(&NodeIndex::new(16)));
+        assert!(required.contains(&NodeIndex::new(18)));
+        assert!(!required.contains(&NodeIndex::new(15)));
+        assert!(!required.contains(&NodeIndex::new(17)));
+        assert_eq!(required.len(), 14);
+    }
+
+    #[test]
+    fn incremental_transitive_dependencies() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+        let graph = storage.graph();
+
+        let mut transitive = vec![BTreeSet::new(); graph.capacity().0];
+        let mut visitor = Topo::new(graph);
+
+        while let Some(index) = visitor.next(graph) {
+            transitive[index.index()] = storage.transitive_dependencies(&transitive, index);
+
+            let mut expected = storage.required_nodes(once(index));
+            expected.remove(&index);
+
+            assert_eq!(transitive[index.index()], expected);
+        }
+    }
+
+    #[test]
+    fn local_context_dirs() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+
+        let context_dirs = |index| {
+            let dependencies = storage.required_nodes(once(index));
+
+            storage.context_dirs(index, &dependencies)
+        };
+
+        assert_eq!(
+            context_dirs(NodeIndex::new(15)),
+            vec![PathBuf::from("/context")]
+        );
+
+        assert_eq!(context_dirs(NodeIndex::new(13)), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn build_script_context_dirs() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+        let node = &storage.graph()[NodeIndex::new(11)];
+        let context_dirs = vec![PathBuf::from("/context/multi-bin")];
+
+        assert_eq!(
+            storage.build_script_context_dirs(node, &context_dirs),
+            context_dirs
+        );
+
+        let storage = storage.with_workspace_build_scripts(vec!["multi-bin".into()]);
+        let node = &storage.graph()[NodeIndex::new(11)];
+
+        assert_eq!(
+            storage.build_script_context_dirs(node, &context_dirs),
+            vec![PathBuf::from("/context")]
+        );
+
+        assert!(storage.build_script_context_dirs(node, &[]).is_empty());
+    }
+
+    #[test]
+    fn pipelined_dependencies() {
+        let storage = MockStorage::pipelined(Profile::ReleaseBinaries);
+        let nodes = storage.serialize_nodes(storage.outputs().map(|output| output.index));
+
+        let operations = |index: usize| -> Vec<Op> {
+            Terminal::with(nodes[index].clone().unwrap())
+                .into_definition()
+                .def
+                .iter()
+                .filter_map(|bytes| pb::Op::decode(bytes.as_slice()).unwrap().op)
+                .collect()
+        };
+
+        let copied = |operations: &[Op]| -> Vec<String> {
+            operations
+                .iter()
+                .filter_map(|op| match op {
+                    Op::File(file) => Some(file),
+                    _ => None,
+                })
+                .flat_map(|file| &file.actions)
+                .filter_map(|action| match action.action {
+                    Some(Action::Copy(ref copy)) => Some(copy.src.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let copied_file = |copied: &[String], name: &str| {
+            copied
+                .iter()
+                .any(|path| path.ends_with(&format!("/debug/deps/{}", name)))
+        };
+
+        // `openssl-sys` doesn't link `libc`, so it's compiled against the metadata only.
+        let library = operations(14);
+        let library_copied = copied(&library);
+
+        assert!(copied_file(
+            &library_copied,
+            "liblibc-af6b81a75f6a7aeb.rmeta"
+        ));
+        assert!(!copied_file(
+            &library_copied,
+            "liblibc-af6b81a75f6a7aeb.rlib"
+        ));
+
+        assert!(library.iter().any(|op| match op {
+            Op::Exec(exec) => exec
+                .meta
+                .as_ref()
+                .unwrap()
+                .args
+                .contains(&String::from("--emit=dep-info,metadata")),
+
+            _ => false,
+        }));
+
+        // The binary links both libraries.
+        let binary_copied = copied(&operations(15));
+
+        assert!(copied_file(&binary_copied, "liblibc-af6b81a75f6a7aeb.rlib"));
+        assert!(copied_file(
+            &binary_copied,
+            "libopenssl_sys-ee7eaf9d748340a6.rlib"
+        ));
+        assert!(copied_file(
+            &binary_copied,
+            "libopenssl_sys-ee7eaf9d748340a6.rmeta"
+        ));
+    }
+
+    #[test]
+    fn nested_context_dirs() {
+        let dirs = vec![
+            Path::new("/context/member-1"),
+            Path::new("/context/member-1/nested"),
+            Path::new("/context/member-2"),
+            Path::new("/context/member-10"),
+        ];
+
+        assert_eq!(
+            outermost_dirs(dirs.into_iter().collect()),
+            vec![
+                PathBuf::from("/context/member-1"),
+                PathBuf::from("/context/member-10"),
+                PathBuf::from("/context/member-2"),
+            ]
+        );
+    }
+}
@@ -17,17 +17,6 @@ pub trait SourceQuery: WharfDatabase + PrettyPrintQuery {
         )
     }
 
-    fn output_source(&self) -> Option<OperationOutput<'_>> {
-        self.source_llb(
-            self.config().output(),
-            self.config()
-                .output()
-                .pre_install_commands()
-                .map(Vec::as_ref)
-                .unwrap_or_default(),
-        )
-    }
-
     fn source_llb<'a>(
         &self,
         config: &'a dyn BaseImageConfig,
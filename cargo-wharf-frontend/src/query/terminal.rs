@@ -1,4 +1,5 @@
-use std::iter::empty;
+use std::collections::BTreeSet;
+use std::mem::take;
 use std::path::{Path, PathBuf};
 
 use either::Either;
@@ -8,108 +9,204 @@ use petgraph::prelude::*;
 
 use buildkit_llb::prelude::*;
 
-use crate::config::BaseImageConfig;
+use crate::config::{BaseImageConfig, BinaryDefinition, CustomCommand, FilePermissions, StripKind};
 use crate::graph::{Node, NodeKind, PrimitiveNodeKind};
-use crate::shared::{tools, TARGET_PATH};
+use crate::shared::{tools, CONTEXT_PATH, TARGET_PATH};
 
+use super::crates::CrateSources;
 use super::print::{PrettyPrintQuery, PrintKind};
+use super::sbom::SbomQuery;
 use super::{Profile, SerializationQuery, WharfDatabase};
 
+const STAGING_PATH: &str = "/staging";
+const BINARIES_PATH: &str = "/wharf/binaries";
+const LIBRARIES_PATH: &str = "/wharf/libraries";
+const STRIP_INPUT_PATH: &str = "/wharf/binary";
+const STRIP_OUTPUT_PATH: &str = "/wharf/stripped";
+const DEBUGINFO_PATH: &str = "/usr/lib/debug";
+const TRANSFORM_PATH: &str = "/wharf";
+const TRANSFORM_BINARY_NAME: &str = "binary";
+const BUILD_INFO_PATH: &str = "/etc/wharf/build-info.json";
+const SBOM_PATH: &str = "/etc/wharf/sbom.cdx.json";
+const LICENSES_PATH: &str = "/usr/share/licenses";
+
 pub struct OutputMapping<'a> {
     from: LayerPath<'a, PathBuf>,
     to: PathBuf,
+    permissions: FilePermissions,
 }
 
 type NodesCache<'a> = Vec<Option<OperationOutput<'a>>>;
 
+pub enum OutputStep<'a> {
+    Command(&'a CustomCommand),
+    Compose,
+    ComposeDebugInfo,
+    NormalizeTimestamps(i64),
+
+    /// Doesn't produce a layer.
+    ImageConfig,
+}
+
 pub struct BuildOutput<'a> {
     pub index: NodeIndex,
     pub node: &'a Node,
     pub path: PathBuf,
+    pub binary: Option<&'a BinaryDefinition>,
 }
 
-pub trait TerminalQuery: WharfDatabase + SerializationQuery + PrettyPrintQuery {
+pub trait TerminalQuery: WharfDatabase + SerializationQuery + PrettyPrintQuery + SbomQuery {
     fn terminal(&self) -> Result<Terminal<'_>, Error> {
-        debug!("serializing all nodes");
-        let nodes = self.serialize_all_nodes();
-        let outputs = self.mapped_outputs(nodes);
+        debug!("serializing the required nodes");
+        let nodes = self.serialize_nodes(self.outputs().map(|output| output.index));
 
-        if outputs.is_empty() {
-            bail!("Nothing to do - no binaries were found");
-        }
+        let (mut outputs, mut output) = if self.config().export_debuginfo() {
+            let outputs = self.debuginfo_mappings(nodes);
 
-        debug!("preparing the final operation");
+            if outputs.is_empty() {
+                bail!("Nothing to do - no binaries with split debug info were found");
+            }
 
-        let operation = FileSystem::sequence().custom_name("Composing the output image");
-        let operation = {
-            outputs.into_iter().fold(operation, |output, mapping| {
-                let (index, layer_path) = match output.last_output_index() {
-                    Some(index) => (index + 1, LayerPath::Own(OwnOutputIdx(index), mapping.to)),
-                    None => (0, self.output_layer_path(mapping.to)),
-                };
+            (outputs, None)
+        } else {
+            let mut outputs = self.mapped_outputs(nodes);
 
-                output.append(
-                    FileSystem::copy()
-                        .from(mapping.from)
-                        .to(OutputIdx(index), layer_path)
-                        .create_path(true),
-                )
-            })
-        };
+            if outputs.is_empty() {
+                bail!("Nothing to do - no binaries were found");
+            }
 
-        let mut commands_iter = {
-            self.config()
-                .output()
-                .post_install_commands()
-                .map(|commands| Either::Left(commands.iter().map(From::from)))
-                .unwrap_or_else(|| Either::Right(empty()))
-        };
+            if self.config().output().bundle_shared_libs() {
+                let libraries = self.bundled_libraries(&outputs);
+                outputs.push(libraries);
+            }
+
+            outputs.append(&mut self.bundled_licenses());
+            outputs.append(&mut self.copy_mappings());
+            outputs.extend(self.build_info_mapping());
+            outputs.extend(self.sbom_mapping());
 
-        if let Some((name, args, display)) = commands_iter.next() {
-            let mut output = {
+            let base = {
                 self.config()
                     .output()
-                    .populate_env(Command::run(name))
-                    .args(args.iter())
-                    .mount(Mount::Layer(
-                        OutputIdx(0),
-                        operation.ref_counted().last_output().unwrap(),
-                        "/",
-                    ))
-                    .custom_name(self.pretty_print(PrintKind::CustomCommand(display)))
-                    .ref_counted()
-                    .output(0)
+                    .image_source()
+                    .map(|source| source.output())
             };
 
-            for (name, args, display) in commands_iter {
-                output = {
-                    self.config()
-                        .output()
-                        .populate_env(Command::run(name))
-                        .args(args.iter())
-                        .mount(Mount::Layer(OutputIdx(0), output, "/"))
-                        .custom_name(self.pretty_print(PrintKind::CustomCommand(display)))
-                        .ref_counted()
-                        .output(0)
-                };
-            }
+            (self.apply_permissions(outputs), base)
+        };
+
+        let destinations: Vec<_> = outputs.iter().map(|mapping| mapping.to.clone()).collect();
+
+        debug!("preparing the final operation");
+        for step in self.output_steps() {
+            output = match step {
+                OutputStep::Command(command) => {
+                    let (name, args, display) = command.into();
+
+                    let command = {
+                        self.config()
+                            .output()
+                            .populate_env(Command::run(name))
+                            .args(args.iter())
+                            .mount(match output {
+                                Some(output) => Mount::Layer(OutputIdx(0), output, "/"),
+                                None => Mount::Scratch(OutputIdx(0), "/"),
+                            })
+                            .custom_name(self.pretty_print(PrintKind::CustomCommand(display)))
+                    };
 
-            return Ok(Terminal::with(output));
+                    Some(command.ref_counted().output(0))
+                }
+
+                OutputStep::Compose | OutputStep::ComposeDebugInfo => {
+                    Some(self.compose(take(&mut outputs), output, &step.description()))
+                }
+
+                OutputStep::NormalizeTimestamps(epoch) => {
+                    let command = {
+                        Command::run("/bin/sh")
+                            .args(vec![
+                                String::from("-c"),
+                                parent_timestamps_script(&destinations, epoch),
+                            ])
+                            .mount(Mount::ReadOnlyLayer(tools::IMAGE.output(), "/"))
+                            .mount(Mount::Layer(OutputIdx(0), output.unwrap(), STAGING_PATH))
+                            .custom_name(step.description())
+                    };
+
+                    Some(command.ref_counted().output(0))
+                }
+
+                OutputStep::ImageConfig => output,
+            };
         }
 
-        Ok(Terminal::with(
-            operation.ref_counted().last_output().unwrap(),
-        ))
+        Ok(Terminal::with(output.unwrap()))
     }
 
-    fn output_layer_path<P>(&self, path: P) -> LayerPath<'_, P>
-    where
-        P: AsRef<Path>,
-    {
-        match self.output_source() {
-            Some(ref output) => LayerPath::Other(output.clone(), path),
-            None => LayerPath::Scratch(path),
+    /// Steps building the output image on top of its base, shared by the operation and the image history.
+    fn output_steps(&self) -> Vec<OutputStep<'_>> {
+        if self.config().export_debuginfo() {
+            return vec![OutputStep::ComposeDebugInfo, OutputStep::ImageConfig];
+        }
+
+        let output = self.config().output();
+        let pre_install = output.pre_install_commands().map(Vec::as_slice);
+        let post_install = output.post_install_commands().map(Vec::as_slice);
+
+        let mut steps: Vec<_> = {
+            pre_install
+                .unwrap_or_default()
+                .iter()
+                .map(OutputStep::Command)
+                .collect()
+        };
+
+        steps.push(OutputStep::Compose);
+
+        if let Some(epoch) = self.config().source_date_epoch() {
+            steps.push(OutputStep::NormalizeTimestamps(epoch));
         }
+
+        steps.extend(
+            post_install
+                .unwrap_or_default()
+                .iter()
+                .map(OutputStep::Command),
+        );
+        steps.push(OutputStep::ImageConfig);
+        steps
+    }
+
+    fn compose<'a>(
+        &self,
+        outputs: Vec<OutputMapping<'a>>,
+        base: Option<OperationOutput<'a>>,
+        name: &str,
+    ) -> OperationOutput<'a> {
+        let operation = FileSystem::sequence().custom_name(name);
+        let operation = {
+            outputs.into_iter().fold(operation, |output, mapping| {
+                let (index, layer_path) = match (output.last_output_index(), &base) {
+                    (Some(index), _) => {
+                        (index + 1, LayerPath::Own(OwnOutputIdx(index), mapping.to))
+                    }
+                    (None, Some(base)) => (0, LayerPath::Other(base.clone(), mapping.to)),
+                    (None, None) => (0, LayerPath::Scratch(mapping.to)),
+                };
+
+                output.append(
+                    FileSystem::copy()
+                        .from(mapping.from)
+                        .to(OutputIdx(index), layer_path)
+                        .create_path(true)
+                        .recursive(true)
+                        .wildcard(true),
+                )
+            })
+        };
+
+        operation.ref_counted().last_output().unwrap()
     }
 
     fn outputs(&self) -> Box<dyn Iterator<Item = BuildOutput<'_>> + '_> {
@@ -120,10 +217,7 @@ pub trait TerminalQuery: WharfDatabase + SerializationQuery + PrettyPrintQuery {
                     .map(move |index| (index, self.graph().node_weight(index).unwrap()))
                     .filter_map(move |(index, node)| {
                         match self.config().find_binary(node.binary_name()?) {
-                            Some(found) => {
-                                Some(BuildOutput::new(index, node, found.destination.clone()))
-                            }
-
+                            Some(found) => Some(BuildOutput::with_binary(index, node, found)),
                             None => None,
                         }
                     }),
@@ -155,22 +249,32 @@ pub trait TerminalQuery: WharfDatabase + SerializationQuery + PrettyPrintQuery {
         })
     }
 
-    fn mapped_outputs<'a>(&self, nodes: NodesCache<'a>) -> Vec<OutputMapping<'a>> {
+    fn mapped_outputs<'a>(&'a self, nodes: NodesCache<'a>) -> Vec<OutputMapping<'a>> {
         let profile = self.config().profile();
         let mut mapped_outputs: Vec<_> = {
             self.outputs()
-                .map(move |BuildOutput { index, node, path }| {
-                    let from = LayerPath::Other(
-                        nodes[index.index()].clone().unwrap(),
-                        node.outputs_iter()
-                            .next()
-                            .unwrap()
-                            .strip_prefix(TARGET_PATH)
-                            .unwrap()
-                            .into(),
-                    );
-
-                    OutputMapping { from, to: path }
+                .map(move |output| {
+                    let (source, path) = build_output_path(&nodes, &output);
+
+                    let from = match output.binary {
+                        Some(binary) => {
+                            let (stripped, _) = self.strip_binary(source, path, binary);
+                            self.transform_binary(stripped, binary)
+                        }
+
+                        None => LayerPath::Other(source, path),
+                    };
+
+                    OutputMapping {
+                        from,
+                        to: output.path,
+                        permissions: {
+                            output
+                                .binary
+                                .map(|binary| binary.permissions)
+                                .unwrap_or_default()
+                        },
+                    }
                 })
                 .collect()
         };
@@ -179,22 +283,571 @@ pub trait TerminalQuery: WharfDatabase + SerializationQuery + PrettyPrintQuery {
             mapped_outputs.push(OutputMapping {
                 from: LayerPath::Other(tools::IMAGE.output(), tools::TEST_RUNNER.into()),
                 to: tools::TEST_RUNNER.into(),
+                permissions: Default::default(),
             });
         }
 
+        if let Some(healthcheck) = self.config().output().healthcheck() {
+            if healthcheck.uses_command(tools::HEALTHCHECK_PROBE) {
+                mapped_outputs.push(OutputMapping {
+                    from: LayerPath::Other(tools::IMAGE.output(), tools::HEALTHCHECK_PROBE.into()),
+                    to: tools::HEALTHCHECK_PROBE.into(),
+                    permissions: Default::default(),
+                });
+            }
+        }
+
         mapped_outputs
     }
+
+    fn debuginfo_mappings<'a>(&'a self, nodes: NodesCache<'a>) -> Vec<OutputMapping<'a>> {
+        self.outputs()
+            .filter_map(move |output| {
+                let binary = output.binary?;
+                let (source, path) = build_output_path(&nodes, &output);
+
+                Some(OutputMapping {
+                    from: self.strip_binary(source, path, binary).1?,
+                    to: debuginfo_path(&binary.destination),
+                    permissions: Default::default(),
+                })
+            })
+            .collect()
+    }
+
+    fn strip_binary<'a>(
+        &'a self,
+        source: OperationOutput<'a>,
+        path: PathBuf,
+        binary: &BinaryDefinition,
+    ) -> (LayerPath<'a, PathBuf>, Option<LayerPath<'a, PathBuf>>) {
+        let split_debuginfo = self.config().binary_split_debuginfo(binary);
+
+        let kind = match (self.config().binary_strip(binary), split_debuginfo) {
+            (Some(kind), _) => kind,
+            (None, true) => StripKind::Debuginfo,
+            (None, false) => return (LayerPath::Other(source, path), None),
+        };
+
+        let name = binary.destination.file_name().unwrap_or_default();
+
+        let stripped = {
+            self.config()
+                .builder()
+                .populate_env(Command::run("/bin/sh"))
+                .args(vec![
+                    String::from("-c"),
+                    strip_script(kind, split_debuginfo, Path::new(name)),
+                ])
+                .mount(Mount::ReadOnlyLayer(self.builder_source().unwrap(), "/"))
+                .mount(Mount::ReadOnlySelector(
+                    source,
+                    PathBuf::from(STRIP_INPUT_PATH),
+                    path,
+                ))
+                .mount(Mount::Scratch(
+                    OutputIdx(0),
+                    PathBuf::from(STRIP_OUTPUT_PATH),
+                ))
+                .custom_name(self.pretty_print(PrintKind::StripBinary(&binary.name)))
+                .ref_counted()
+                .output(0)
+        };
+
+        let debuginfo = if split_debuginfo {
+            Some(LayerPath::Other(stripped.clone(), debuginfo_name(name)))
+        } else {
+            None
+        };
+
+        (LayerPath::Other(stripped, PathBuf::from(name)), debuginfo)
+    }
+
+    fn transform_binary<'a>(
+        &'a self,
+        from: LayerPath<'a, PathBuf>,
+        binary: &'a BinaryDefinition,
+    ) -> LayerPath<'a, PathBuf> {
+        let commands = match binary.transform {
+            Some(ref commands) if !commands.is_empty() => commands,
+            _ => return from,
+        };
+
+        let mut output = {
+            FileSystem::sequence()
+                .custom_name(format!(
+                    "Preparing binary {} for transformation",
+                    binary.name
+                ))
+                .append(
+                    FileSystem::copy()
+                        .from(from)
+                        .to(OutputIdx(0), LayerPath::Scratch(transformed_path())),
+                )
+                .ref_counted()
+                .last_output()
+                .unwrap()
+        };
+
+        for (name, args, display) in commands.iter().map(From::from) {
+            output = {
+                self.config()
+                    .builder()
+                    .populate_env(Command::run(name))
+                    .args(args.iter())
+                    .cwd(TRANSFORM_PATH)
+                    .env(
+                        "WHARF_BINARY",
+                        Path::new(TRANSFORM_PATH)
+                            .join(TRANSFORM_BINARY_NAME)
+                            .to_string_lossy(),
+                    )
+                    .mount(Mount::ReadOnlyLayer(self.builder_source().unwrap(), "/"))
+                    .mount(Mount::Layer(OutputIdx(0), output, TRANSFORM_PATH))
+                    .custom_name(self.pretty_print(PrintKind::CustomCommand(display)))
+                    .ref_counted()
+                    .output(0)
+            };
+        }
+
+        LayerPath::Other(output, transformed_path())
+    }
+
+    fn copy_mappings(&self) -> Vec<OutputMapping<'_>> {
+        let from_context = {
+            self.config()
+                .output()
+                .copy()
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .map(|definition| (self.config().context().source().output(), definition))
+        };
+
+        let builder_source = self.builder_source().unwrap();
+        let from_builder = {
+            self.config()
+                .output()
+                .copy_from_builder()
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .map(move |definition| (builder_source.clone(), definition))
+        };
+
+        from_context
+            .chain(from_builder)
+            .map(|(source, definition)| OutputMapping {
+                from: LayerPath::Other(source, definition.from.clone()),
+                to: definition.to.clone(),
+                permissions: definition.permissions,
+            })
+            .collect()
+    }
+
+    fn bundled_libraries<'a>(&'a self, outputs: &[OutputMapping<'a>]) -> OutputMapping<'a> {
+        let mut args = vec![String::from("--output"), String::from(LIBRARIES_PATH)];
+
+        let mut command = {
+            self.config()
+                .builder()
+                .populate_env(Command::run(tools::BUNDLE_LIBS))
+                .mount(Mount::ReadOnlyLayer(self.builder_source().unwrap(), "/"))
+                .mount(Mount::ReadOnlySelector(
+                    tools::IMAGE.output(),
+                    tools::BUNDLE_LIBS,
+                    tools::BUNDLE_LIBS,
+                ))
+                .mount(Mount::Scratch(OutputIdx(0), LIBRARIES_PATH))
+                .custom_name("Bundling shared libraries")
+        };
+
+        for (index, mapping) in outputs.iter().enumerate() {
+            if let LayerPath::Other(ref output, ref path) = mapping.from {
+                let destination = Path::new(BINARIES_PATH).join(index.to_string());
+
+                args.push(destination.to_string_lossy().into());
+                command = command.mount(Mount::ReadOnlySelector(
+                    output.clone(),
+                    destination,
+                    path.clone(),
+                ));
+            }
+        }
+
+        OutputMapping {
+            from: LayerPath::Other(command.args(args).ref_counted().output(0), "/".into()),
+            to: "/".into(),
+            permissions: Default::default(),
+        }
+    }
+
+    fn bundled_licenses(&self) -> Vec<OutputMapping<'_>> {
+        let licenses = match self.config().output().licenses() {
+            Some(licenses) => licenses,
+            None => return vec![],
+        };
+
+        let destination = {
+            licenses
+                .destination
+                .clone()
+                .unwrap_or_else(|| LICENSES_PATH.into())
+        };
+
+        let mut deny_args = vec![];
+        for license in licenses.deny.iter().flatten() {
+            deny_args.push(String::from("--deny"));
+            deny_args.push(license.clone());
+        }
+
+        let mut sources = CrateSources::new();
+
+        self.outputs()
+            .map(|output| {
+                let name = output.path.file_name().unwrap();
+                let args = {
+                    vec![String::from("--output"), String::from(LICENSES_PATH)]
+                        .into_iter()
+                        .chain(deny_args.iter().cloned())
+                        .chain(
+                            self.third_party_dirs(output.index)
+                                .into_iter()
+                                .map(|dir| dir.to_string_lossy().into()),
+                        )
+                };
+
+                let linked = self.linked_nodes(output.index);
+                let mounts = {
+                    self.crates_source_mounts(
+                        &mut sources,
+                        linked.iter().map(|index| &self.graph()[*index]),
+                    )
+                };
+
+                let mut command = {
+                    self.config()
+                        .builder()
+                        .populate_env(Command::run(tools::COLLECT_LICENSES))
+                        .args(args)
+                        .mount(Mount::ReadOnlyLayer(self.builder_source().unwrap(), "/"))
+                        .mount(Mount::ReadOnlySelector(
+                            tools::IMAGE.output(),
+                            tools::COLLECT_LICENSES,
+                            tools::COLLECT_LICENSES,
+                        ))
+                        .mount(Mount::Scratch(OutputIdx(0), LICENSES_PATH))
+                        .custom_name(format!(
+                            "Collecting licenses of '{}'",
+                            name.to_string_lossy()
+                        ))
+                };
+
+                for mount in mounts {
+                    command = command.mount(mount);
+                }
+
+                OutputMapping {
+                    from: LayerPath::Other(command.ref_counted().output(0), "/".into()),
+                    to: destination.join(name),
+                    permissions: Default::default(),
+                }
+            })
+            .collect()
+    }
+
+    /// Directories of linked crates that don't come from the build context.
+    fn third_party_dirs(&self, index: NodeIndex) -> BTreeSet<&Path> {
+        self.linked_nodes(index)
+            .into_iter()
+            .map(|index| self.graph()[index].manifest_dir())
+            .filter(|dir| !dir.starts_with(CONTEXT_PATH))
+            .collect()
+    }
+
+    fn build_info_mapping(&self) -> Option<OutputMapping<'_>> {
+        let vcs = self.config().output().vcs()?;
+        let name = Path::new(BUILD_INFO_PATH).file_name().unwrap();
+
+        let output = {
+            FileSystem::mkfile(OutputIdx(0), LayerPath::Scratch(Path::new("/").join(name)))
+                .data(serde_json::to_vec_pretty(vcs).unwrap())
+                .into_operation()
+                .custom_name("Writing build info")
+                .ref_counted()
+                .last_output()
+                .unwrap()
+        };
+
+        Some(OutputMapping {
+            from: LayerPath::Other(output, Path::new("/").join(name)),
+            to: BUILD_INFO_PATH.into(),
+            permissions: Default::default(),
+        })
+    }
+
+    fn sbom_mapping(&self) -> Option<OutputMapping<'_>> {
+        if !self.config().output().sbom() {
+            return None;
+        }
+
+        let name = Path::new(SBOM_PATH).file_name().unwrap();
+        let sbom = self.sbom(self.outputs());
+
+        let output = {
+            FileSystem::mkfile(OutputIdx(0), LayerPath::Scratch(Path::new("/").join(name)))
+                .data(serde_json::to_vec_pretty(&sbom).unwrap())
+                .into_operation()
+                .custom_name("Writing the software bill of materials")
+                .ref_counted()
+                .last_output()
+                .unwrap()
+        };
+
+        Some(OutputMapping {
+            from: LayerPath::Other(output, Path::new("/").join(name)),
+            to: SBOM_PATH.into(),
+            permissions: Default::default(),
+        })
+    }
+
+    fn apply_permissions<'a>(&self, outputs: Vec<OutputMapping<'a>>) -> Vec<OutputMapping<'a>> {
+        let epoch = self.config().source_date_epoch();
+
+        if epoch.is_none() && outputs.iter().all(|mapping| mapping.permissions.is_empty()) {
+            return outputs;
+        }
+
+        let mut staging =
+            FileSystem::sequence().custom_name("Staging files with custom permissions");
+        let mut staged_outputs = Vec::with_capacity(outputs.len());
+        let mut permissions = vec![];
+
+        for mapping in outputs {
+            if epoch.is_none() && mapping.permissions.is_empty() {
+                staged_outputs.push(Either::Left(mapping));
+                continue;
+            }
+
+            let (index, layer_path) = match staging.last_output_index() {
+                Some(index) => (index + 1, LayerPath::Own(OwnOutputIdx(index), &mapping.to)),
+                None => (0, LayerPath::Scratch(&mapping.to)),
+            };
+
+            staging = staging.append(
+                FileSystem::copy()
+                    .from(mapping.from)
+                    .to(OutputIdx(index), layer_path)
+                    .create_path(true)
+                    .recursive(true)
+                    .wildcard(true),
+            );
+
+            permissions.push((mapping.to.clone(), mapping.permissions));
+            staged_outputs.push(Either::Right(mapping.to));
+        }
+
+        let mut script = permissions_script(&permissions);
+
+        if let Some(epoch) = epoch {
+            if !script.is_empty() {
+                script.push_str(" && ");
+            }
+
+            // Copying preserves modification time, so the composed files will get it too.
+            script.push_str(&format!(
+                "find {} -exec touch -h -d @{} {{}} +",
+                STAGING_PATH, epoch
+            ));
+        }
+
+        let staged = {
+            Command::run("/bin/sh")
+                .args(vec![String::from("-c"), script])
+                .mount(Mount::ReadOnlyLayer(tools::IMAGE.output(), "/"))
+                .mount(Mount::Layer(
+                    OutputIdx(0),
+                    staging.ref_counted().last_output().unwrap(),
+                    STAGING_PATH,
+                ))
+                .custom_name("Applying file permissions")
+                .ref_counted()
+                .output(0)
+        };
+
+        staged_outputs
+            .into_iter()
+            .map(|output| match output {
+                Either::Left(mapping) => mapping,
+                Either::Right(to) => OutputMapping {
+                    from: LayerPath::Other(staged.clone(), to.clone()),
+                    to,
+                    permissions: Default::default(),
+                },
+            })
+            .collect()
+    }
 }
 
 impl<'a> BuildOutput<'a> {
     pub fn new(index: NodeIndex, node: &'a Node, path: PathBuf) -> Self {
-        Self { index, node, path }
+        Self {
+            index,
+            node,
+            path,
+            binary: None,
+        }
+    }
+
+    pub fn with_binary(index: NodeIndex, node: &'a Node, binary: &'a BinaryDefinition) -> Self {
+        Self {
+            index,
+            node,
+            path: binary.destination.clone(),
+            binary: Some(binary),
+        }
+    }
+}
+
+fn build_output_path<'a>(
+    nodes: &NodesCache<'a>,
+    output: &BuildOutput,
+) -> (OperationOutput<'a>, PathBuf) {
+    let path = {
+        output
+            .node
+            .outputs_iter()
+            .next()
+            .unwrap()
+            .strip_prefix(TARGET_PATH)
+            .unwrap()
+            .into()
+    };
+
+    (nodes[output.index.index()].clone().unwrap(), path)
+}
+
+fn transformed_path() -> PathBuf {
+    Path::new("/").join(TRANSFORM_BINARY_NAME)
+}
+
+fn debuginfo_name<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut name = path.as_ref().as_os_str().to_owned();
+    name.push(".debug");
+
+    name.into()
+}
+
+fn debuginfo_path(destination: &Path) -> PathBuf {
+    Path::new(DEBUGINFO_PATH).join(debuginfo_name(
+        destination.strip_prefix("/").unwrap_or(destination),
+    ))
+}
+
+fn strip_script(kind: StripKind, split_debuginfo: bool, name: &Path) -> String {
+    let input = Path::new(STRIP_INPUT_PATH);
+    let output = Path::new(STRIP_OUTPUT_PATH).join(name);
+    let debuginfo = Path::new(STRIP_OUTPUT_PATH).join(debuginfo_name(name));
+
+    let flag = match kind {
+        StripKind::Symbols => "--strip-all",
+        StripKind::Debuginfo => "--strip-debug",
+    };
+
+    if !split_debuginfo {
+        return format!(
+            "objcopy {} '{}' '{}'",
+            flag,
+            input.display(),
+            output.display()
+        );
+    }
+
+    // The debug link has to point to the file name used for the exported debug info.
+    format!(
+        "objcopy --only-keep-debug '{input}' '{debuginfo}' \
+         && objcopy {flag} --add-gnu-debuglink='{debuginfo}' '{input}' '{output}'",
+        flag = flag,
+        input = input.display(),
+        output = output.display(),
+        debuginfo = debuginfo.display(),
+    )
+}
+
+impl<'a> OutputStep<'a> {
+    pub fn is_layer(&self) -> bool {
+        !matches!(self, OutputStep::ImageConfig)
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            OutputStep::Command(command) => command.display_text().into_owned(),
+            OutputStep::Compose => "Composing the output image".into(),
+            OutputStep::ComposeDebugInfo => "Composing the debug info image".into(),
+            OutputStep::NormalizeTimestamps(_) => "Normalizing directories timestamps".into(),
+            OutputStep::ImageConfig => "Setting the image config".into(),
+        }
+    }
+}
+
+fn parent_timestamps_script(destinations: &[PathBuf], epoch: i64) -> String {
+    let mut parents = BTreeSet::new();
+
+    for destination in destinations {
+        parents.extend(destination.ancestors().skip(1));
+    }
+
+    let parents: Vec<_> = {
+        parents
+            .into_iter()
+            .map(|path| Path::new(STAGING_PATH).join(path.strip_prefix("/").unwrap_or(path)))
+            .map(|path| format!("'{}'", path.display()))
+            .collect()
+    };
+
+    format!("touch -h -d @{} {}", epoch, parents.join(" "))
+}
+
+fn permissions_script(permissions: &[(PathBuf, FilePermissions)]) -> String {
+    let mut commands = vec![];
+
+    for (path, permissions) in permissions {
+        let path = Path::new(STAGING_PATH).join(path.strip_prefix("/").unwrap_or(path));
+
+        let owner = match (permissions.owner, permissions.group) {
+            (Some(owner), Some(group)) => Some(format!("{}:{}", owner, group)),
+            (Some(owner), None) => Some(owner.to_string()),
+            (None, Some(group)) => Some(format!(":{}", group)),
+            (None, None) => None,
+        };
+
+        // Changing the owner might reset `setuid` and `setgid` bits, so it has to go first.
+        // Symlinks are changed themselves, without touching their targets.
+        if let Some(owner) = owner {
+            commands.push(format!("chown -R -h {} '{}'", owner, path.display()));
+        }
+
+        // The mode is meant for files: applying it to directories could make them untraversable.
+        if let Some(mode) = permissions.mode {
+            commands.push(format!(
+                "find '{}' -type f -exec chmod {:o} {{}} +",
+                path.display(),
+                mode
+            ));
+        }
     }
+
+    commands.join(" && ")
 }
 
 #[cfg(test)]
 mod tests {
+    use prost::Message;
+
+    use buildkit_proto::pb::{self, op::Op};
+
     use super::*;
+    use crate::config::CustomCommandKind;
     use crate::query::tests::MockStorage;
 
     #[test]
@@ -210,6 +863,147 @@ mod tests {
         );
     }
 
+    #[test]
+    fn third_party_crates() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+
+        assert_eq!(
+            storage.third_party_dirs(NodeIndex::new(15)),
+            vec![
+                Path::new("/home//.cargo/registry/src/github.com-1ecc6299db9ec823/libc-0.2.62"),
+                Path::new(
+                    "/home//.cargo/registry/src/github.com-1ecc6299db9ec823/openssl-sys-0.9.50"
+                ),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn permissions_scripting() {
+        let permissions = vec![
+            (
+                PathBuf::from("/usr/bin/binary-1"),
+                FilePermissions {
+                    mode: Some(0o4755),
+                    owner: Some(1000),
+                    group: None,
+                },
+            ),
+            (
+                PathBuf::from("/bin/binary-2"),
+                FilePermissions {
+                    mode: None,
+                    owner: Some(0),
+                    group: Some(1000),
+                },
+            ),
+            (
+                PathBuf::from("/usr/share/assets"),
+                FilePermissions {
+                    mode: Some(0o644),
+                    owner: None,
+                    group: Some(1000),
+                },
+            ),
+        ];
+
+        assert_eq!(
+            permissions_script(&permissions),
+            "chown -R -h 1000 '/staging/usr/bin/binary-1' \
+             && find '/staging/usr/bin/binary-1' -type f -exec chmod 4755 {} + \
+             && chown -R -h 0:1000 '/staging/bin/binary-2' \
+             && chown -R -h :1000 '/staging/usr/share/assets' \
+             && find '/staging/usr/share/assets' -type f -exec chmod 644 {} +"
+        );
+    }
+
+    #[test]
+    fn timestamps_scripting() {
+        let destinations = vec![
+            PathBuf::from("/usr/bin/binary-1"),
+            PathBuf::from("/usr/local/bin/binary-2"),
+        ];
+
+        assert_eq!(
+            parent_timestamps_script(&destinations, 1_500_000_000),
+            "touch -h -d @1500000000 '/staging/' '/staging/usr' '/staging/usr/bin' \
+             '/staging/usr/local' '/staging/usr/local/bin'"
+        );
+    }
+
+    #[test]
+    fn strip_scripting() {
+        assert_eq!(
+            strip_script(StripKind::Symbols, false, Path::new("binary-1")),
+            "objcopy --strip-all '/wharf/binary' '/wharf/stripped/binary-1'"
+        );
+
+        assert_eq!(
+            strip_script(StripKind::Debuginfo, true, Path::new("binary-1")),
+            "objcopy --only-keep-debug '/wharf/binary' '/wharf/stripped/binary-1.debug' \
+             && objcopy --strip-debug --add-gnu-debuglink='/wharf/stripped/binary-1.debug' \
+             '/wharf/binary' '/wharf/stripped/binary-1'"
+        );
+
+        assert_eq!(
+            debuginfo_path(Path::new("/usr/bin/binary-1")),
+            PathBuf::from("/usr/lib/debug/usr/bin/binary-1.debug")
+        );
+    }
+
+    #[test]
+    fn binary_transformation() {
+        let storage = MockStorage::mocked(Profile::ReleaseBinaries);
+        let binary = BinaryDefinition {
+            name: "bin-1".into(),
+            destination: "/usr/bin/mock-binary-1".into(),
+            strip: None,
+            split_debuginfo: None,
+            transform: Some(vec![CustomCommand {
+                display: None,
+                kind: CustomCommandKind::Command(vec!["upx".into(), "binary".into()]),
+            }]),
+            permissions: Default::default(),
+        };
+
+        let from = LayerPath::Other(
+            storage.config().context().source().output(),
+            "/bin-1".into(),
+        );
+        let output = match storage.transform_binary(from, &binary) {
+            LayerPath::Other(output, path) => {
+                assert_eq!(path, transformed_path());
+                output
+            }
+
+            _ => unreachable!(),
+        };
+
+        let definition = Terminal::with(output).into_definition();
+        let exec = {
+            definition
+                .def
+                .iter()
+                .filter_map(|bytes| pb::Op::decode(bytes.as_slice()).unwrap().op)
+                .find_map(|op| match op {
+                    Op::Exec(exec) => Some(exec),
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let mounts: Vec<_> = {
+            exec.mounts
+                .iter()
+                .map(|mount| (mount.dest.as_str(), mount.readonly, mount.output))
+                .collect()
+        };
+
+        assert_eq!(mounts, vec![("/", true, -1), (TRANSFORM_PATH, false, 0)]);
+    }
+
     #[test]
     fn query_tests() {
         let storage = MockStorage::mocked(Profile::ReleaseTests);
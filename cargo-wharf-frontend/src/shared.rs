@@ -1,21 +1,8 @@
 use lazy_static::*;
 
-use buildkit_llb::ops::source::{ImageSource, LocalSource};
+use buildkit_llb::ops::source::ImageSource;
 use buildkit_llb::prelude::*;
 
-lazy_static! {
-    pub static ref CONTEXT: LocalSource = {
-        Source::local("context")
-            .custom_name("Using build context")
-            .add_exclude_pattern("**/target")
-    };
-    pub static ref DOCKERFILE: LocalSource = {
-        Source::local("dockerfile")
-            .custom_name("Using build context")
-            .add_exclude_pattern("**/target")
-    };
-}
-
 pub const CONTEXT_PATH: &str = "/context";
 pub const DOCKERFILE_PATH: &str = "/dockerfile";
 pub const TARGET_PATH: &str = "/target";
@@ -32,4 +19,9 @@ pub mod tools {
     pub const BUILDSCRIPT_APPLY: &str = "/usr/local/bin/cargo-buildscript-apply";
     pub const BUILD_PLAN: &str = "/usr/local/bin/cargo-build-plan";
     pub const TEST_RUNNER: &str = "/usr/local/bin/cargo-test-runner";
+    pub const BUNDLE_LIBS: &str = "/usr/local/bin/cargo-bundle-libs";
+    pub const HEALTHCHECK_PROBE: &str = "/usr/local/bin/cargo-healthcheck-probe";
+    pub const COLLECT_LICENSES: &str = "/usr/local/bin/cargo-collect-licenses";
+    pub const AUDIT_CHECK: &str = "/usr/local/bin/cargo-audit-check";
+    pub const STUB_TARGETS: &str = "/usr/local/bin/cargo-stub-targets";
 }